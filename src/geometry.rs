@@ -1,10 +1,28 @@
+use serde::{Deserialize, Serialize};
 use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::sync::atomic::{AtomicU64, Ordering};
 
-#[derive(Debug, Clone, Copy)]
+/// the floating point type used for all geometry and shading. f64 unless
+/// the `f32` feature is enabled, which trades precision for speed
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
+#[cfg(feature = "f32")]
+pub use std::f32::consts;
+/// mathematical constants of type Float
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts;
+
+/// equality is exact: components are compared with IEEE float equality, so
+/// 0.0 equals -0.0 and a vector with a NaN component never equals anything,
+/// including itself. use approx_eq to compare with a tolerance
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Vec3 {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
 }
 
 impl Add for Vec3 {
@@ -39,10 +57,10 @@ impl Sub for Vec3 {
     }
 }
 
-impl Mul<f64> for Vec3 {
+impl Mul<Float> for Vec3 {
     type Output = Vec3;
 
-    fn mul(self, rhs: f64) -> Self::Output {
+    fn mul(self, rhs: Float) -> Self::Output {
         Vec3 {
             x: rhs * self.x,
             y: rhs * self.y,
@@ -51,7 +69,7 @@ impl Mul<f64> for Vec3 {
     }
 }
 
-impl Mul<Vec3> for f64 {
+impl Mul<Vec3> for Float {
     type Output = Vec3;
 
     fn mul(self, rhs: Vec3) -> Self::Output {
@@ -59,10 +77,10 @@ impl Mul<Vec3> for f64 {
     }
 }
 
-impl Div<f64> for Vec3 {
+impl Div<Float> for Vec3 {
     type Output = Vec3;
 
-    fn div(self, rhs: f64) -> Self::Output {
+    fn div(self, rhs: Float) -> Self::Output {
         Vec3 {
             x: self.x / rhs,
             y: self.y / rhs,
@@ -93,16 +111,16 @@ impl Vec3 {
         z: 1.0,
     };
 
-    pub fn new(x: f64, y: f64, z: f64) -> Self {
+    pub fn new(x: Float, y: Float, z: Float) -> Self {
         Vec3 { x, y, z }
     }
 
-    pub fn dot(self, rhs: Vec3) -> f64 {
+    pub fn dot(self, rhs: Vec3) -> Float {
         self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
     }
 
-    pub fn length(self) -> f64 {
-        f64::sqrt(self.dot(self))
+    pub fn length(self) -> Float {
+        Float::sqrt(self.dot(self))
     }
 
     pub fn normalize(self) -> Vec3 {
@@ -110,16 +128,88 @@ impl Vec3 {
     }
 
     /// angle between self and rhs in radians
-    pub fn angle_between(self, rhs: Vec3) -> f64 {
-        f64::acos(self.dot(rhs) / (self.length() * rhs.length()))
+    pub fn angle_between(self, rhs: Vec3) -> Float {
+        // rounding can push the cosine of nearly parallel vectors just past
+        // 1 in magnitude, where acos is NaN
+        let cos = self.dot(rhs) / (self.length() * rhs.length());
+        Float::acos(cos.clamp(-1.0, 1.0))
+    }
+
+    /// whether every component of self is within epsilon of rhs
+    pub fn approx_eq(self, rhs: Vec3, epsilon: Float) -> bool {
+        (self.x - rhs.x).abs() <= epsilon
+            && (self.y - rhs.y).abs() <= epsilon
+            && (self.z - rhs.z).abs() <= epsilon
     }
 
     pub fn cross(self, rhs: Vec3) -> Vec3 {
         Vec3 {
             x: self.y * rhs.z - self.z * rhs.y,
             y: -(self.x * rhs.z - self.z * rhs.x),
-            z: self.x * rhs.y - self.y * rhs.z,
+            z: self.x * rhs.y - self.y * rhs.x,
+        }
+    }
+
+    /// rotates self by angle radians around the unit vector axis, counter
+    /// clockwise when looking down the axis towards the origin (rodrigues'
+    /// rotation formula)
+    pub fn rotate_around(self, axis: Vec3, angle: Float) -> Vec3 {
+        let (sin, cos) = angle.sin_cos();
+        self * cos + axis.cross(self) * sin + axis * axis.dot(self) * (1.0 - cos)
+    }
+
+    /// interpolates linearly from self at t = 0 to rhs at t = 1
+    pub fn lerp(self, rhs: Vec3, t: Float) -> Vec3 {
+        self + (rhs - self) * t
+    }
+
+    /// interpolates the direction of self at t = 0 to that of rhs at t = 1
+    /// along the great circle between them at a constant angular speed,
+    /// returning a unit vector. neither may be zero. nearly parallel
+    /// directions fall back to a normalized lerp, and opposite ones turn
+    /// around an arbitrary perpendicular axis
+    pub fn slerp(self, rhs: Vec3, t: Float) -> Vec3 {
+        let a = self.normalize();
+        let b = rhs.normalize();
+        let angle = a.angle_between(b);
+        let sin = angle.sin();
+        if sin <= 4096.0 * Float::EPSILON {
+            if a.dot(b) > 0.0 {
+                return a.lerp(b, t).normalize();
+            }
+            let other = if a.x.abs() < 0.9 { Vec3::I } else { Vec3::J };
+            let axis = a.cross(other).normalize();
+            return a.rotate_around(axis, t * consts::PI);
+        }
+        a * (((1.0 - t) * angle).sin() / sin) + b * ((t * angle).sin() / sin)
+    }
+
+    /// the unit vector in the direction given by spherical angles in
+    /// radians, in the y-up convention: theta is the angle down from +y, in
+    /// [0, pi], and phi is the angle around the y axis from +x towards +z
+    pub fn from_spherical(theta: Float, phi: Float) -> Vec3 {
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        Vec3::new(sin_theta * cos_phi, cos_theta, sin_theta * sin_phi)
+    }
+
+    /// the spherical angles (theta, phi) of the direction of self, in the
+    /// convention of from_spherical, with phi in [-pi, pi]. phi is 0 on the
+    /// y axis, and theta is NaN for the zero vector
+    pub fn to_spherical(self) -> (Float, Float) {
+        let theta = Float::acos((self.y / self.length()).clamp(-1.0, 1.0));
+        if self.x == 0.0 && self.z == 0.0 {
+            return (theta, 0.0);
         }
+        (theta, Float::atan2(self.z, self.x))
+    }
+
+    /// the mirror image of the point self across the plane through
+    /// plane_point with the given normal. for a direction rather than a
+    /// point, use a plane_point of Vec3::ZERO
+    pub fn reflect_about_plane(self, plane_point: Vec3, normal: Vec3) -> Vec3 {
+        let normal = normal.normalize();
+        self - 2.0 * (self - plane_point).dot(normal) * normal
     }
 }
 
@@ -142,6 +232,18 @@ impl Mul<Vec3> for Mat3x3 {
     }
 }
 
+impl Mul for Mat3x3 {
+    type Output = Mat3x3;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Mat3x3 {
+            col1: self * rhs.col1,
+            col2: self * rhs.col2,
+            col3: self * rhs.col3,
+        }
+    }
+}
+
 impl Mat3x3 {
     pub const IDENTITY: Mat3x3 = Mat3x3 {
         col1: Vec3::I,
@@ -153,72 +255,97 @@ impl Mat3x3 {
         Mat3x3 { col1, col2, col3 }
     }
 
+    /// the transpose, which is the inverse for a rotation matrix
+    pub fn transpose(self) -> Self {
+        Mat3x3 {
+            col1: Vec3::new(self.col1.x, self.col2.x, self.col3.x),
+            col2: Vec3::new(self.col1.y, self.col2.y, self.col3.y),
+            col3: Vec3::new(self.col1.z, self.col2.z, self.col3.z),
+        }
+    }
+
     /// constructs the rotation matrix about the x axis by theta (in radians)
-    pub fn x_rot_mat(theta: f64) -> Self {
+    pub fn x_rot_mat(theta: Float) -> Self {
         Mat3x3 {
             col1: Vec3::I,
             col2: Vec3 {
                 x: 0.0,
-                y: f64::cos(theta),
-                z: f64::sin(theta),
+                y: Float::cos(theta),
+                z: Float::sin(theta),
             },
             col3: Vec3 {
                 x: 0.0,
-                y: -f64::sin(theta),
-                z: f64::cos(theta),
+                y: -Float::sin(theta),
+                z: Float::cos(theta),
             },
         }
     }
 
     /// constructs the rotation matrix about the y axis by theta (in radians)
-    pub fn y_rot_mat(theta: f64) -> Self {
+    pub fn y_rot_mat(theta: Float) -> Self {
         Mat3x3 {
             col1: Vec3 {
-                x: f64::cos(theta),
+                x: Float::cos(theta),
                 y: 0.0,
-                z: -f64::sin(theta),
+                z: -Float::sin(theta),
             },
             col2: Vec3::J,
             col3: Vec3 {
-                x: f64::sin(theta),
+                x: Float::sin(theta),
                 y: 0.0,
-                z: f64::cos(theta),
+                z: Float::cos(theta),
             },
         }
     }
 
-    // adapted from https://stackoverflow.com/a/18574797
-    // get the rotation matrix of rotating to dir from up
-    // up must be nonzero
-    // this is broken :()
-    // pub fn rotation_mat(dir: Vec3, up: Vec3) -> Self {
-    //     let x_axis = up.cross(dir).normalize();
-    //     if x_axis.x.is_nan() {
-    //         return Mat3x3::IDENTITY;
-    //     }
-    //     let y_axis = dir.cross(x_axis).normalize();
-
-    //     Mat3x3 {
-    //         col1: Vec3 {
-    //             x: x_axis.x,
-    //             y: y_axis.x,
-    //             z: dir.x,
-    //         },
-    //         col2: Vec3 {
-    //             x: x_axis.y,
-    //             y: y_axis.y,
-    //             z: dir.y,
-    //         },
-    //         col3: Vec3 {
-    //             x: x_axis.z,
-    //             y: y_axis.z,
-    //             z: dir.z,
-    //         },
-    //     }
-    // }
+    /// constructs the rotation matrix about the z axis by theta (in radians)
+    pub fn z_rot_mat(theta: Float) -> Self {
+        Mat3x3 {
+            col1: Vec3 {
+                x: Float::cos(theta),
+                y: Float::sin(theta),
+                z: 0.0,
+            },
+            col2: Vec3 {
+                x: -Float::sin(theta),
+                y: Float::cos(theta),
+                z: 0.0,
+            },
+            col3: Vec3::K,
+        }
+    }
+
+    /// constructs the rotation matrix that turns the direction from onto the
+    /// direction to by the smallest angle. neither may be zero
+    pub fn rotation_between(from: Vec3, to: Vec3) -> Self {
+        let a = from.normalize();
+        let b = to.normalize();
+        let v = a.cross(b);
+        let c = a.dot(b);
+
+        if c <= -1.0 + 4096.0 * Float::EPSILON {
+            // opposite directions, so turn halfway around any perpendicular axis
+            let other = if a.x.abs() < 0.9 { Vec3::I } else { Vec3::J };
+            let axis = a.cross(other).normalize();
+            return Mat3x3 {
+                col1: 2.0 * axis.x * axis - Vec3::I,
+                col2: 2.0 * axis.y * axis - Vec3::J,
+                col3: 2.0 * axis.z * axis - Vec3::K,
+            };
+        }
+
+        // Rodrigues' formula, R = cI + [v]x + v v^T / (1 + c)
+        let k = 1.0 / (1.0 + c);
+        Mat3x3 {
+            col1: Vec3::new(c + k * v.x * v.x, v.z + k * v.x * v.y, -v.y + k * v.x * v.z),
+            col2: Vec3::new(-v.z + k * v.y * v.x, c + k * v.y * v.y, v.x + k * v.y * v.z),
+            col3: Vec3::new(v.y + k * v.z * v.x, -v.x + k * v.z * v.y, c + k * v.z * v.z),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// equality compares every channel, including alpha, exactly
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -226,10 +353,19 @@ pub struct Color {
     pub a: u8,
 }
 
-/// multiplies u by f and clamps the product to the valid range of u8 values
-fn clamped_mul(u: u8, f: f64) -> u8 {
-    let product = ((u as f64) * f).clamp(u8::MIN as f64, u8::MAX as f64);
-    product as u8
+/// multiplies u by f and clamps the product to the valid range of u8 values.
+/// a NaN product becomes 0 and infinite products clamp to the range ends
+fn clamped_mul(u: u8, f: Float) -> u8 {
+    dithered_mul(u, f, 0.0)
+}
+
+/// like clamped_mul, but adds offset before the product is truncated
+fn dithered_mul(u: u8, f: Float, offset: Float) -> u8 {
+    let product = (u as Float) * f + offset;
+    if product.is_nan() {
+        return 0;
+    }
+    product.clamp(u8::MIN as Float, u8::MAX as Float) as u8
 }
 
 fn clamped_add(u: u8, v: u8) -> u8 {
@@ -250,26 +386,42 @@ impl Add for Color {
     }
 }
 
-impl Mul<f64> for Color {
+/// subtracts each channel, saturating at 0
+impl Sub for Color {
+    type Output = Color;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Color {
+            r: self.r.saturating_sub(rhs.r),
+            g: self.g.saturating_sub(rhs.g),
+            b: self.b.saturating_sub(rhs.b),
+            a: self.a.saturating_sub(rhs.a),
+        }
+    }
+}
+
+/// scales the color channels, as when lighting a surface, leaving alpha as
+/// it is
+impl Mul<Float> for Color {
     type Output = Color;
-    fn mul(self, rhs: f64) -> Self::Output {
+    fn mul(self, rhs: Float) -> Self::Output {
         Color {
             r: clamped_mul(self.r, rhs),
             g: clamped_mul(self.g, rhs),
             b: clamped_mul(self.b, rhs),
-            a: clamped_mul(self.a, rhs),
+            a: self.a,
         }
     }
 }
 
-impl Mul<Color> for f64 {
+impl Mul<Color> for Float {
     type Output = Color;
     fn mul(self, rhs: Color) -> Self::Output {
         Color {
             r: clamped_mul(rhs.r, self),
             g: clamped_mul(rhs.g, self),
             b: clamped_mul(rhs.b, self),
-            a: clamped_mul(rhs.a, self),
+            a: rhs.a,
         }
     }
 }
@@ -311,30 +463,332 @@ impl Color {
     pub fn as_u8_slice(self) -> [u8; 4] {
         [self.r, self.g, self.b, self.a]
     }
+    /// multiplies each channel by the matching channel of rhs, treating
+    /// channels as fractions of 0xff
+    pub fn mul_componentwise(self, rhs: Color) -> Color {
+        let mul = |u: u8, v: u8| ((u as u16 * v as u16 + 0x7f) / 0xff) as u8;
+        Color {
+            r: mul(self.r, rhs.r),
+            g: mul(self.g, rhs.g),
+            b: mul(self.b, rhs.b),
+            a: mul(self.a, rhs.a),
+        }
+    }
+    /// multiplies each color channel by rhs like Mul, adding offset in
+    /// [0, 1) to each product before it is truncated. varying the offset
+    /// between pixels dithers the quantization error. alpha is unchanged
+    pub fn mul_dithered(self, rhs: Float, offset: Float) -> Color {
+        Color {
+            r: dithered_mul(self.r, rhs, offset),
+            g: dithered_mul(self.g, rhs, offset),
+            b: dithered_mul(self.b, rhs, offset),
+            a: self.a,
+        }
+    }
+    /// blends each channel linearly from self at t = 0 to rhs at t = 1
+    pub fn lerp(self, rhs: Color, t: Float) -> Color {
+        let lerp = |u: u8, v: u8| (u as Float + (v as Float - u as Float) * t).round() as u8;
+        Color {
+            r: lerp(self.r, rhs.r),
+            g: lerp(self.g, rhs.g),
+            b: lerp(self.b, rhs.b),
+            a: lerp(self.a, rhs.a),
+        }
+    }
+    /// the per-channel mean of colors, rounded to the nearest value
+    pub fn average(colors: &[Color]) -> Color {
+        let n = colors.len() as u32;
+        if n == 0 {
+            return Color::BLACK;
+        }
+        let channel_mean = |channel: fn(&Color) -> u8| {
+            let sum: u32 = colors.iter().map(|c| channel(c) as u32).sum();
+            ((sum + n / 2) / n) as u8
+        };
+        Color {
+            r: channel_mean(|c| c.r),
+            g: channel_mean(|c| c.g),
+            b: channel_mean(|c| c.b),
+            a: channel_mean(|c| c.a),
+        }
+    }
+    /// the red, green and blue channels converted from sRGB to linear light
+    /// in [0, 1] with the piecewise sRGB transfer function
+    pub fn to_linear_vec3(self) -> Vec3 {
+        self.map_channels(|c| srgb_to_linear(c as Float / 255.0))
+    }
+    /// an opaque color from linear light in [0, 1], encoded with the
+    /// piecewise sRGB transfer function. out of range values are clamped
+    pub fn from_linear_vec3(linear: Vec3) -> Color {
+        Color::from_encoded(linear, linear_to_srgb)
+    }
+    /// like to_linear_vec3 but decoding with a plain power of gamma, the
+    /// common approximation of sRGB with gamma 2.2
+    pub fn to_linear_vec3_gamma(self, gamma: Float) -> Vec3 {
+        self.map_channels(|c| (c as Float / 255.0).powf(gamma))
+    }
+    /// like from_linear_vec3 but encoding with a plain power of 1 / gamma
+    pub fn from_linear_vec3_gamma(linear: Vec3, gamma: Float) -> Color {
+        Color::from_encoded(linear, |c| c.powf(1.0 / gamma))
+    }
+    fn map_channels(self, f: impl Fn(u8) -> Float) -> Vec3 {
+        Vec3::new(f(self.r), f(self.g), f(self.b))
+    }
+    fn from_encoded(linear: Vec3, encode: impl Fn(Float) -> Float) -> Color {
+        let channel = |c: Float| (encode(c.clamp(0.0, 1.0)) * 255.0).round() as u8;
+        Color::new(
+            channel(linear.x),
+            channel(linear.y),
+            channel(linear.z),
+            0xff,
+        )
+    }
 }
 
+/// decodes a channel in [0, 1] from sRGB to linear light
+pub fn srgb_to_linear(c: Float) -> Float {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// encodes a channel of linear light in [0, 1] as sRGB
+pub fn linear_to_srgb(c: Float) -> Float {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// a stable handle to an object added to a scene with Scene::add_object,
+/// which stays valid while other objects are added and removed. handles are
+/// unique across all scenes and never reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectId(u64);
+
+impl ObjectId {
+    /// a handle no object has had before
+    fn fresh() -> ObjectId {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        ObjectId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// a handle for a sphere read from json, as if it were added with
+/// Scene::add_object
+fn fresh_id() -> Option<ObjectId> {
+    Some(ObjectId::fresh())
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Sphere {
-    pub radius: f64,
+    pub radius: Float,
     pub center: Vec3,
     pub color: Color,
     pub specularity: Specularity,
     /// 0.0 (not reflective at all) to 1.0 (a perfect mirror)
-    pub reflectiveness: f64,
+    pub reflectiveness: Float,
+    /// multiplies the reflected color, e.g. a warm tint for gold. white
+    /// leaves reflections untinted
+    pub reflection_tint: Color,
+    /// whether the sphere is seen by rays from the camera
+    pub visible_primary: bool,
+    /// whether the sphere blocks light from reaching other surfaces
+    pub cast_shadows: bool,
+    /// whether the sphere appears in reflections
+    pub visible_reflection: bool,
+    /// the chance a ray from the camera hitting the sphere stops at it
+    /// rather than passing straight through. averaged over many samples,
+    /// values below 1 make the sphere look translucent. shadow and
+    /// reflection rays always stop at it
+    pub opacity: Float,
+    /// the handle Scene::add_object gave the sphere. None for spheres not
+    /// added that way, including copies made with reflect_about_plane or
+    /// grid. spheres read from json get fresh handles
+    #[serde(skip, default = "fresh_id")]
+    pub id: Option<ObjectId>,
 }
 
+/// the surface properties of a sphere, bundled for reuse
 #[derive(Clone, Copy)]
+pub struct Material {
+    pub color: Color,
+    pub specularity: Specularity,
+    pub reflectiveness: Float,
+    pub reflection_tint: Color,
+    pub opacity: Float,
+}
+
+impl Default for Material {
+    /// white, matte, opaque and not reflective
+    fn default() -> Self {
+        Material {
+            color: Color::WHITE,
+            specularity: Specularity::Matte,
+            reflectiveness: 0.0,
+            reflection_tint: Color::WHITE,
+            opacity: 1.0,
+        }
+    }
+}
+
+impl Material {
+    /// the names of the presets, in the order they are documented
+    pub const PRESETS: [&'static str; 5] = ["plastic", "rubber", "metal", "mirror", "glass"];
+
+    /// a material with parameters for a common look, or None for an unknown
+    /// name:
+    ///
+    /// - plastic: white, specular 100, reflectiveness 0.05
+    /// - rubber: dark gray, specular 10
+    /// - metal: metallic silver, specular 500, reflectiveness 0.5
+    /// - mirror: white, specular 1000, reflectiveness 0.9
+    /// - glass: white, specular 1000, reflectiveness 0.1, opacity 0.2. there
+    ///   is no refraction, so this is only an approximation
+    pub fn preset(name: &str) -> Option<Material> {
+        let silver = Color::new(0xc0, 0xc0, 0xc0, 0xff);
+        let base = Material::default();
+        Some(match name {
+            "plastic" => Material {
+                specularity: Specularity::Specular(100.0),
+                reflectiveness: 0.05,
+                ..base
+            },
+            "rubber" => Material {
+                color: Color::new(0x30, 0x30, 0x30, 0xff),
+                specularity: Specularity::Specular(10.0),
+                ..base
+            },
+            "metal" => Material {
+                color: silver,
+                specularity: Specularity::Metallic {
+                    exponent: 500.0,
+                    tint: silver,
+                },
+                reflectiveness: 0.5,
+                ..base
+            },
+            "mirror" => Material {
+                specularity: Specularity::Specular(1000.0),
+                reflectiveness: 0.9,
+                ..base
+            },
+            "glass" => Material {
+                specularity: Specularity::Specular(1000.0),
+                reflectiveness: 0.1,
+                opacity: 0.2,
+                ..base
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// the index of refraction of air
+pub const IOR_AIR: Float = 1.000293;
+/// the index of refraction of water
+pub const IOR_WATER: Float = 1.333;
+/// the index of refraction of common crown glass
+pub const IOR_GLASS: Float = 1.52;
+/// the index of refraction of diamond
+pub const IOR_DIAMOND: Float = 2.417;
+
+/// the names refractive_index_for knows, with their indices of refraction
+pub const REFRACTIVE_INDICES: [(&str, Float); 4] = [
+    ("air", IOR_AIR),
+    ("water", IOR_WATER),
+    ("glass", IOR_GLASS),
+    ("diamond", IOR_DIAMOND),
+];
+
+/// the index of refraction of a named material, or None for an unknown name
+pub fn refractive_index_for(name: &str) -> Option<Float> {
+    REFRACTIVE_INDICES
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|&(_, ior)| ior)
+}
+
+/// what a ray is being traced for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RayKind {
+    Primary,
+    Reflection,
+    Shadow,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub enum Specularity {
-    Specular(f64),
+    Specular(Float),
     Matte,
+    /// a metal, which has no diffuse reflection. its highlight and mirror
+    /// reflections are tinted with the metal's color, which replaces the
+    /// sphere's color
+    Metallic {
+        exponent: Float,
+        tint: Color,
+    },
+    /// a highlight stretched along the surface, like brushed metal, with
+    /// exponent_u along the surface in the direction of brush and exponent_v
+    /// across it. the lower exponent is the direction the highlight is
+    /// stretched in, and equal exponents give the Blinn-Phong highlight.
+    /// with a metal tint, the surface is a metal as with Metallic
+    Anisotropic {
+        exponent_u: Float,
+        exponent_v: Float,
+        brush: Vec3,
+        metal: Option<Color>,
+    },
+}
+
+impl Specularity {
+    /// the range specular exponents are clamped to. around 10 gives a broad
+    /// sheen, 100 a plastic-like highlight and 1000 a tight, polished one
+    pub const MIN_EXPONENT: Float = 1.0;
+    pub const MAX_EXPONENT: Float = 10_000.0;
+
+    /// the specular exponent clamped to the sane range, or None for matte
+    /// and anisotropic surfaces and NaN exponents
+    pub fn exponent(self) -> Option<Float> {
+        match self {
+            Specularity::Specular(s) | Specularity::Metallic { exponent: s, .. } if !s.is_nan() => {
+                Some(s.clamp(Self::MIN_EXPONENT, Self::MAX_EXPONENT))
+            }
+            _ => None,
+        }
+    }
+
+    /// the color of a metallic surface, or None for other surfaces
+    pub fn metal_tint(self) -> Option<Color> {
+        match self {
+            Specularity::Metallic { tint, .. } => Some(tint),
+            Specularity::Anisotropic { metal, .. } => metal,
+            _ => None,
+        }
+    }
+}
+
+/// how specular highlights are computed
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub enum SpecularModel {
+    /// the angle between the reflected light and the view direction
+    #[default]
+    Phong,
+    /// the angle between the normal and the halfway vector of the light and
+    /// view directions
+    BlinnPhong,
 }
 
 impl Sphere {
     pub fn new(
-        radius: f64,
+        radius: Float,
         center: Vec3,
         color: Color,
         specularity: Specularity,
-        reflectiveness: f64,
+        reflectiveness: Float,
     ) -> Self {
         Sphere {
             radius,
@@ -342,73 +796,1028 @@ impl Sphere {
             color,
             specularity,
             reflectiveness,
+            reflection_tint: Color::WHITE,
+            visible_primary: true,
+            cast_shadows: true,
+            visible_reflection: true,
+            opacity: 1.0,
+            id: None,
+        }
+    }
+
+    /// a sphere with the surface properties of material
+    pub fn with_material(radius: Float, center: Vec3, material: Material) -> Self {
+        Sphere {
+            reflection_tint: material.reflection_tint,
+            opacity: material.opacity,
+            ..Sphere::new(
+                radius,
+                center,
+                material.color,
+                material.specularity,
+                material.reflectiveness,
+            )
+        }
+    }
+
+    /// the surface properties of the sphere, as with_material takes them
+    pub fn material(&self) -> Material {
+        Material {
+            color: self.color,
+            specularity: self.specularity,
+            reflectiveness: self.reflectiveness,
+            reflection_tint: self.reflection_tint,
+            opacity: self.opacity,
+        }
+    }
+
+    /// whether rays of the given kind can hit the sphere
+    pub fn visible_to(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Primary => self.visible_primary,
+            RayKind::Reflection => self.visible_reflection,
+            RayKind::Shadow => self.cast_shadows,
+        }
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        let extent = Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+
+    /// whether p lies inside or on the surface of the sphere
+    pub fn contains_point(&self, p: Vec3) -> bool {
+        let d = p - self.center;
+        d.dot(d) <= self.radius * self.radius
+    }
+
+    /// a copy of the sphere mirrored across the plane through plane_point
+    /// with the given normal
+    pub fn reflect_about_plane(&self, plane_point: Vec3, normal: Vec3) -> Sphere {
+        Sphere {
+            center: self.center.reflect_about_plane(plane_point, normal),
+            id: None,
+            ..*self
         }
     }
+
+    /// copies of the sphere in a grid of counts[0] x counts[1] x counts[2]
+    /// along the x, y and z axes, spaced apart by the matching component of
+    /// spacing. the first copy is at the sphere's center and the grid extends
+    /// towards positive spacing
+    pub fn grid(&self, counts: [u32; 3], spacing: Vec3) -> Vec<Sphere> {
+        let [nx, ny, nz] = counts;
+        (0..nx)
+            .flat_map(|i| (0..ny).flat_map(move |j| (0..nz).map(move |k| (i, j, k))))
+            .map(|(i, j, k)| Sphere {
+                center: self.center
+                    + Vec3::new(
+                        i as Float * spacing.x,
+                        j as Float * spacing.y,
+                        k as Float * spacing.z,
+                    ),
+                id: None,
+                ..*self
+            })
+            .collect()
+    }
+}
+
+/// an axis-aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
 }
 
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Aabb { min, max }
+    }
+
+    /// the smallest box containing both self and rhs
+    pub fn union(self, rhs: Aabb) -> Aabb {
+        Aabb {
+            min: Vec3::new(
+                self.min.x.min(rhs.min.x),
+                self.min.y.min(rhs.min.y),
+                self.min.z.min(rhs.min.z),
+            ),
+            max: Vec3::new(
+                self.max.x.max(rhs.max.x),
+                self.max.y.max(rhs.max.y),
+                self.max.z.max(rhs.max.z),
+            ),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+
+    /// the eight corners, where bit 0, 1 and 2 of the index choose the max
+    /// rather than the min x, y and z coordinate
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (min, max) = (self.min, self.max);
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+            Vec3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        })
+    }
+
+    /// the twelve edges as pairs of indices into corners
+    pub fn edges() -> [(usize, usize); 12] {
+        [
+            (0, 1),
+            (2, 3),
+            (4, 5),
+            (6, 7),
+            (0, 2),
+            (1, 3),
+            (4, 6),
+            (5, 7),
+            (0, 4),
+            (1, 5),
+            (2, 6),
+            (3, 7),
+        ]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Surface {
-    pub w: f64,
-    pub h: f64,
+    pub w: Float,
+    pub h: Float,
 }
 
 impl Surface {
-    pub fn new(w: f64, h: f64) -> Self {
+    pub fn new(w: Float, h: Float) -> Self {
         Surface { w, h }
     }
 }
 
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct AmbientLight {
-    pub intensity: f64,
+    pub intensity: Float,
+    /// tints the light, e.g. a cool blue fill. white leaves it neutral
+    pub color: Color,
 }
 
 impl AmbientLight {
-    pub fn new(intensity: f64) -> Self {
-        AmbientLight { intensity }
+    pub fn new(intensity: Float) -> Self {
+        AmbientLight {
+            intensity,
+            color: Color::WHITE,
+        }
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PointLight {
-    pub intensity: f64,
+    pub intensity: Float,
     pub position: Vec3,
+    /// radius of the sphere the light is emitted from. 0 is an ideal point
+    /// with hard shadows; larger radii give softer penumbrae
+    pub radius: Float,
+    /// how the light moves and pulses over time. None keeps it still
+    pub animation: Option<LightAnimation>,
 }
 
 impl PointLight {
-    pub fn new(intensity: f64, position: Vec3) -> Self {
+    pub fn new(intensity: Float, position: Vec3) -> Self {
+        PointLight {
+            intensity,
+            position,
+            radius: 0.0,
+            animation: None,
+        }
+    }
+
+    /// the still light this light is at the given time in seconds
+    pub fn at(&self, time: Float) -> PointLight {
+        let (mut intensity, mut position) = (self.intensity, self.position);
+        if let Some(animation) = &self.animation {
+            position = animation.position_at(time).unwrap_or(position);
+            intensity *= animation.pulse_at(time);
+        }
         PointLight {
             intensity,
             position,
+            radius: self.radius,
+            animation: None,
+        }
+    }
+}
+
+/// a path through keyframed positions and a sine pulse in intensity
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct LightAnimation {
+    /// (time, position) pairs sorted by time. the position is interpolated
+    /// linearly between them and held before the first and after the last
+    pub keyframes: Vec<(Float, Vec3)>,
+    /// the relative amplitude and the frequency in hertz of a sine wave the
+    /// intensity is scaled by, e.g. (0.5, 1.0) swings between 50% and 150%
+    /// once a second
+    pub pulse: Option<(Float, Float)>,
+}
+
+impl LightAnimation {
+    /// the interpolated position at time, or None without keyframes.
+    /// keyframes sharing a time jump straight to the later one
+    pub fn position_at(&self, time: Float) -> Option<Vec3> {
+        let next = self.keyframes.iter().position(|&(t, _)| t > time);
+        match next {
+            Some(0) => self.keyframes.first().map(|&(_, p)| p),
+            Some(i) => {
+                let ((t0, p0), (t1, p1)) = (self.keyframes[i - 1], self.keyframes[i]);
+                let span = t1 - t0;
+                if span <= 0.0 || span.is_nan() {
+                    return Some(p1);
+                }
+                Some(p0 + (p1 - p0) * ((time - t0) / span))
+            }
+            None => self.keyframes.last().map(|&(_, p)| p),
+        }
+    }
+
+    /// the factor the intensity is scaled by at time
+    pub fn pulse_at(&self, time: Float) -> Float {
+        match self.pulse {
+            Some((amplitude, frequency)) => {
+                1.0 + amplitude * Float::sin(2.0 * consts::PI * frequency * time)
+            }
+            None => 1.0,
         }
     }
 }
 
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct DirectionalLight {
-    pub intensity: f64,
+    pub intensity: Float,
     pub dir: Vec3,
 }
 
 impl DirectionalLight {
-    pub fn new(intensity: f64, dir: Vec3) -> Self {
+    pub fn new(intensity: Float, dir: Vec3) -> Self {
         DirectionalLight { intensity, dir }
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Light {
     Ambient(AmbientLight),
     Point(PointLight),
     Directional(DirectionalLight),
 }
 
+/// what a ray that hits nothing sees
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Background {
+    Solid(Color),
+    /// blends from bottom for rays pointing straight down to top for rays
+    /// pointing straight up
+    Gradient {
+        top: Color,
+        bottom: Color,
+    },
+    /// blends from horizon for horizontal rays to zenith straight up and to
+    /// nadir straight down
+    ProceduralSky {
+        zenith: Color,
+        horizon: Color,
+        nadir: Color,
+    },
+}
+
+impl Background {
+    /// the color seen along dir
+    pub fn color(&self, dir: Vec3) -> Color {
+        match *self {
+            Background::Solid(color) => color,
+            Background::Gradient { top, bottom } => {
+                let t = (dir.normalize().y + 1.0) / 2.0;
+                bottom.lerp(top, t.clamp(0.0, 1.0))
+            }
+            Background::ProceduralSky {
+                zenith,
+                horizon,
+                nadir,
+            } => {
+                let y = dir.normalize().y.clamp(-1.0, 1.0);
+                if y >= 0.0 {
+                    horizon.lerp(zenith, y)
+                } else {
+                    horizon.lerp(nadir, -y)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Scene {
     pub spheres: Vec<Sphere>,
-    pub bg_color: Color,
-    pub canvas: Surface,
+    pub background: Background,
     pub viewport: Surface,
-    pub camera_dist: f64,
+    pub camera_dist: Float,
     pub lights: Vec<Light>,
+    pub specular_model: SpecularModel,
+    /// upper bound on the lighting intensity at any point, used to suppress
+    /// fireflies from extreme specular highlights. None leaves it unbounded
+    pub max_intensity: Option<Float>,
+    /// strength of the light surfaces pick up from the background in the
+    /// direction of their normal, tinting them with the sky's color. None
+    /// disables sky lighting
+    pub sky_light: Option<Float>,
+}
+
+impl Scene {
+    /// the camera distance fit_camera_dist picks as a fraction of the radius
+    /// of the sphere around the scene
+    const CAMERA_DIST_FRACTION: Float = 0.01;
+
+    /// the smallest box containing every sphere, or None if there are none
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.spheres
+            .iter()
+            .map(Sphere::bounding_box)
+            .reduce(Aabb::union)
+    }
+
+    /// adds sphere to the scene, returning a handle to it that stays valid
+    /// until it is removed
+    pub fn add_object(&mut self, mut sphere: Sphere) -> ObjectId {
+        let id = ObjectId::fresh();
+        sphere.id = Some(id);
+        self.spheres.push(sphere);
+        id
+    }
+
+    /// removes the object with the given handle from the scene and returns
+    /// it, or None if there is no such object. the handles of other objects
+    /// stay valid, though their indices in spheres may shift
+    pub fn remove_object(&mut self, id: ObjectId) -> Option<Sphere> {
+        let index = self.index_of(id)?;
+        Some(self.spheres.remove(index))
+    }
+
+    /// the index in spheres of the object with the given handle
+    pub fn index_of(&self, id: ObjectId) -> Option<usize> {
+        self.spheres.iter().position(|sphere| sphere.id == Some(id))
+    }
+
+    /// finds the index of the first sphere containing p
+    pub fn object_at(&self, p: Vec3) -> Option<usize> {
+        self.spheres
+            .iter()
+            .position(|sphere| sphere.contains_point(p))
+    }
+
+    /// sets the camera distance in proportion to the size of the scene, so
+    /// the near limit of primary rays suits scenes of any scale. the viewport
+    /// is scaled with it, keeping the field of view
+    pub fn fit_camera_dist(&mut self) {
+        let Some(bbox) = self.bounding_box() else {
+            return;
+        };
+        let radius = (bbox.max - bbox.min).length() / 2.0;
+        if radius <= 0.0 || !radius.is_finite() {
+            return;
+        }
+        let camera_dist = radius * Self::CAMERA_DIST_FRACTION;
+        let scale = camera_dist / self.camera_dist;
+        self.viewport = Surface::new(self.viewport.w * scale, self.viewport.h * scale);
+        self.camera_dist = camera_dist;
+    }
+
+    /// adds a mirrored copy of every sphere across the plane through
+    /// plane_point with the given normal, making the scene symmetric about it.
+    /// each copy is a new object with its own handle
+    pub fn add_mirrored(&mut self, plane_point: Vec3, normal: Vec3) {
+        let mirrored: Vec<Sphere> = self
+            .spheres
+            .iter()
+            .map(|sphere| sphere.reflect_about_plane(plane_point, normal))
+            .collect();
+        for sphere in mirrored {
+            self.add_object(sphere);
+        }
+    }
+
+    /// appends the spheres and lights of other, so scenes authored in parts
+    /// can be rendered together. everything else, including the background
+    /// and viewport, is kept from self. spheres are kept even where they
+    /// coincide with one already in the scene
+    pub fn merge(&mut self, other: Scene) {
+        self.spheres.extend(other.spheres);
+        self.lights.extend(other.lights);
+    }
+}
+
+/// the convention world coordinates follow. with +y up and +x to the right,
+/// an unrotated camera looks along +z in a left-handed world, as this crate
+/// always has, and along -z in a right-handed one, as most modelling tools
+/// and OpenGL expect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Handedness {
+    #[default]
+    Left,
+    Right,
+}
+
+impl Handedness {
+    /// the matrix taking the left-handed y-up frame to this convention's,
+    /// which mirrors z for a right-handed world
+    pub fn mirror(self) -> Mat3x3 {
+        match self {
+            Handedness::Left => Mat3x3::IDENTITY,
+            Handedness::Right => Mat3x3::new(Vec3::I, Vec3::J, -Vec3::K),
+        }
+    }
 }
 
 pub struct Camera {
     pub position: Vec3,
-    pub y_rot: f64,
-    pub x_rot: f64,
+    pub y_rot: Float,
+    pub x_rot: Float,
+    pub z_rot: Float,
+    /// the world's up direction, which the camera yaws around. +y by default;
+    /// use +z for assets authored z-up
+    pub up: Vec3,
+    /// whether the world is left- or right-handed. the camera turns the same
+    /// way on screen for either, while camera space stays left-handed
+    pub handedness: Handedness,
+}
+
+impl Camera {
+    /// the rotation from the default y-up frame to the frame with self.up as
+    /// up, followed by yaw. its columns are the camera's level right, up and
+    /// forward directions, which movement follows. for a right-handed world
+    /// it also mirrors z, so it is then a reflection rather than a rotation
+    pub fn heading(&self) -> Mat3x3 {
+        Mat3x3::rotation_between(Vec3::J, self.up)
+            * self.handedness.mirror()
+            * Mat3x3::y_rot_mat(self.y_rot)
+    }
+
+    /// the transform from camera space, where the camera looks along +z with
+    /// +y up, to world space. roll (z_rot) is applied first, then pitch
+    /// (x_rot), then yaw (y_rot) about the up axis, so the columns are the
+    /// camera's right, up and forward directions in world space. it is
+    /// orthogonal, so the transpose is the inverse
+    pub fn view_matrix(&self) -> Mat3x3 {
+        self.heading() * Mat3x3::x_rot_mat(self.x_rot) * Mat3x3::z_rot_mat(self.z_rot)
+    }
+
+    /// an unrotated camera in a world of the given handedness that fits all
+    /// of bbox in view for a scene with the given viewport and camera
+    /// distance. it is placed so that the sphere around bbox fits inside the
+    /// narrower of the horizontal and vertical fields of view. an empty
+    /// scene has no bounding box, so there is nothing to frame
+    pub fn frame(
+        bbox: Aabb,
+        viewport: &Surface,
+        camera_dist: Float,
+        handedness: Handedness,
+    ) -> Camera {
+        let radius = (bbox.max - bbox.min).length() / 2.0;
+        let half_fov = Float::atan(viewport.w.min(viewport.h) / 2.0 / camera_dist);
+        let distance = radius / Float::sin(half_fov);
+        let mut camera = Camera {
+            position: bbox.center(),
+            y_rot: 0.0,
+            x_rot: 0.0,
+            z_rot: 0.0,
+            up: Vec3::J,
+            handedness,
+        };
+        camera.position = camera.position - camera.view_matrix() * Vec3::K * distance;
+        camera
+    }
+
+    /// moves the camera step units forward while changing camera_dist, and
+    /// with it the field of view, so that things target_dist ahead keep the
+    /// same size on screen while nearer and farther things grow or shrink.
+    /// returns the new distance to the target
+    pub fn dolly_zoom(
+        &mut self,
+        camera_dist: &mut Float,
+        target_dist: Float,
+        step: Float,
+    ) -> Float {
+        let new_target_dist = target_dist - step;
+        // on-screen size is proportional to camera_dist / distance
+        *camera_dist *= new_target_dist / target_dist;
+        self.position = self.position + self.view_matrix() * Vec3::K * step;
+        new_target_dist
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use consts::{FRAC_PI_2, PI};
+
+    const EPSILON: Float = 1e-5;
+
+    fn sphere(radius: Float, center: Vec3) -> Sphere {
+        Sphere::new(radius, center, Color::WHITE, Specularity::Matte, 0.0)
+    }
+
+    fn scene(spheres: Vec<Sphere>) -> Scene {
+        Scene {
+            spheres,
+            background: Background::Solid(Color::WHITE),
+            viewport: Surface::new(1.0, 1.0),
+            camera_dist: 1.0,
+            lights: Vec::new(),
+            specular_model: SpecularModel::Phong,
+            max_intensity: None,
+            sky_light: None,
+        }
+    }
+
+    fn camera() -> Camera {
+        Camera {
+            position: Vec3::ZERO,
+            y_rot: 0.0,
+            x_rot: 0.0,
+            z_rot: 0.0,
+            up: Vec3::J,
+            handedness: Handedness::Left,
+        }
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!(a.approx_eq(b, EPSILON), "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn vec3_equality_is_exact() {
+        assert_eq!(Vec3::new(1.0, 2.0, 3.0), Vec3::new(1.0, 2.0, 3.0));
+        assert_ne!(Vec3::new(1.0, 2.0, 3.0), Vec3::new(1.0, 2.0, 3.0 + 1e-3));
+        assert_eq!(Vec3::new(0.0, 0.0, 0.0), Vec3::new(-0.0, 0.0, 0.0));
+        let nan = Vec3::new(Float::NAN, 0.0, 0.0);
+        assert_ne!(nan, nan);
+    }
+
+    #[test]
+    fn color_equality_compares_every_channel() {
+        assert_eq!(Color::new(1, 2, 3, 4), Color::new(1, 2, 3, 4));
+        assert_ne!(Color::new(1, 2, 3, 4), Color::new(1, 2, 3, 5));
+        assert_ne!(Color::RED, Color::GREEN);
+    }
+
+    #[test]
+    fn angle_between_is_never_nan() {
+        let v = Vec3::new(0.1, 0.2, 0.3);
+        // acos is steep near 1, so angles near 0 and pi are only as precise
+        // as the square root of the float epsilon
+        assert!(v.angle_between(v).abs() < 1e-3);
+        assert!((v.angle_between(-v) - PI).abs() < 1e-3);
+        // rounding pushes the cosine of these just past 1
+        let angle = v.angle_between(v * 3.0);
+        assert!(!angle.is_nan());
+        assert!(angle.abs() < 1e-3);
+    }
+
+    #[test]
+    fn rotate_around_follows_the_right_hand_rule() {
+        assert_close(Vec3::I.rotate_around(Vec3::K, FRAC_PI_2), Vec3::J);
+        let v = Vec3::new(1.0, 2.0, 3.0);
+        assert_close(v.rotate_around(v.normalize(), 1.0), v);
+        let axis = Vec3::new(1.0, 1.0, 0.0).normalize();
+        let rotated = v.rotate_around(axis, 0.7);
+        assert!((rotated.length() - v.length()).abs() < EPSILON);
+    }
+
+    #[test]
+    fn slerp_keeps_a_constant_angular_speed() {
+        let half = Vec3::I.slerp(Vec3::J, 0.5);
+        assert_close(half, Vec3::new(1.0, 1.0, 0.0).normalize());
+        assert_close(Vec3::I.slerp(Vec3::J, 0.0), Vec3::I);
+        assert_close(Vec3::I.slerp(Vec3::J, 1.0), Vec3::J);
+        // nearly parallel directions fall back to lerp instead of dividing
+        // by a tiny sine
+        let near = Vec3::new(1.0, 1e-9, 0.0);
+        let v = Vec3::I.slerp(near, 0.5);
+        assert!((v.length() - 1.0).abs() < EPSILON);
+        assert_close(v, Vec3::I);
+        // opposite directions still give a unit vector
+        let v = Vec3::I.slerp(-Vec3::I, 0.5);
+        assert!((v.length() - 1.0).abs() < EPSILON);
+        assert!(v.dot(Vec3::I).abs() < EPSILON);
+    }
+
+    #[test]
+    fn spherical_coordinates_round_trip() {
+        for v in [
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(-0.5, 0.1, -2.0),
+            Vec3::new(0.0, -1.0, 1.0),
+        ] {
+            let (theta, phi) = v.to_spherical();
+            assert_close(Vec3::from_spherical(theta, phi), v.normalize());
+        }
+    }
+
+    #[test]
+    fn spherical_coordinates_of_poles_and_equator() {
+        assert_eq!(Vec3::J.to_spherical(), (0.0, 0.0));
+        let (theta, phi) = (-Vec3::J).to_spherical();
+        assert!((theta - PI).abs() < EPSILON && phi == 0.0);
+        let (theta, phi) = Vec3::I.to_spherical();
+        assert!((theta - FRAC_PI_2).abs() < EPSILON && phi.abs() < EPSILON);
+        let (theta, phi) = Vec3::K.to_spherical();
+        assert!((theta - FRAC_PI_2).abs() < EPSILON && (phi - FRAC_PI_2).abs() < EPSILON);
+        assert_close(Vec3::from_spherical(0.0, 1.0), Vec3::J);
+    }
+
+    #[test]
+    fn reflecting_about_a_plane() {
+        let p = Vec3::new(1.0, 2.0, 3.0);
+        assert_eq!(
+            p.reflect_about_plane(Vec3::ZERO, Vec3::K),
+            Vec3::new(1.0, 2.0, -3.0)
+        );
+
+        let (point, normal) = (Vec3::new(1.0, -2.0, 0.5), Vec3::new(1.0, 1.0, 2.0));
+        let mirrored = p.reflect_about_plane(point, normal);
+        assert!(!mirrored.approx_eq(p, EPSILON));
+        assert_close(mirrored.reflect_about_plane(point, normal), p);
+
+        let on_plane = point + normal.cross(Vec3::I);
+        assert_close(on_plane.reflect_about_plane(point, normal), on_plane);
+    }
+
+    #[test]
+    fn subtracting_colors_saturates() {
+        let c = Color::new(10, 200, 30, 0xff);
+        assert_eq!(c - Color::new(20, 100, 30, 0), Color::new(0, 100, 0, 0xff));
+        assert_eq!(c - Color::new(0, 0, 0, 0), c);
+        assert_eq!(Color::WHITE.mul_componentwise(c), c);
+        assert_eq!(
+            c.mul_componentwise(Color::new(0, 0, 0, 0)),
+            Color::new(0, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn scaling_a_color_keeps_its_alpha() {
+        let c = Color::new(100, 100, 100, 0x80);
+        assert_eq!(c * 2.0, Color::new(200, 200, 200, 0x80));
+        assert_eq!(c * Float::NAN, Color::new(0, 0, 0, 0x80));
+        assert_eq!(c * Float::INFINITY, Color::new(0xff, 0xff, 0xff, 0x80));
+    }
+
+    #[test]
+    fn srgb_conversion_matches_reference_values() {
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+        assert!((linear_to_srgb(0.214) - 0.5).abs() < 1e-3);
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < EPSILON);
+        // a plain 2.2 power is close to, but not the same as, the real curve
+        let gamma = Color::new(0x80, 0x80, 0x80, 0xff).to_linear_vec3_gamma(2.2);
+        let srgb = Color::new(0x80, 0x80, 0x80, 0xff).to_linear_vec3();
+        assert!((gamma.x - srgb.x).abs() > 1e-4);
+        assert!((gamma.x - srgb.x).abs() < 1e-2);
+    }
+
+    #[test]
+    fn srgb_conversion_round_trips_every_level() {
+        for v in 0..=255 {
+            let c = Color::new(v, v, v, 0xff);
+            assert_eq!(Color::from_linear_vec3(c.to_linear_vec3()), c);
+            assert_eq!(
+                Color::from_linear_vec3_gamma(c.to_linear_vec3_gamma(2.2), 2.2),
+                c
+            );
+        }
+    }
+
+    #[test]
+    fn sphere_contains_points_up_to_its_surface() {
+        let s = sphere(1.0, Vec3::new(1.0, 0.0, 0.0));
+        assert!(s.contains_point(s.center));
+        assert!(s.contains_point(Vec3::new(1.999, 0.0, 0.0)));
+        assert!(!s.contains_point(Vec3::new(2.001, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn object_at_finds_the_sphere_containing_a_point() {
+        let scene = scene(vec![
+            sphere(1.0, Vec3::ZERO),
+            sphere(1.0, Vec3::new(1.5, 0.0, 0.0)),
+        ]);
+        assert_eq!(scene.object_at(Vec3::new(-0.5, 0.0, 0.0)), Some(0));
+        assert_eq!(scene.object_at(Vec3::new(2.0, 0.0, 0.0)), Some(1));
+        // where they overlap the first wins
+        assert_eq!(scene.object_at(Vec3::new(0.75, 0.0, 0.0)), Some(0));
+        assert_eq!(scene.object_at(Vec3::new(0.0, 5.0, 0.0)), None);
+    }
+
+    #[test]
+    fn bounding_box_encloses_every_sphere() {
+        let scene = scene(vec![
+            sphere(1.0, Vec3::new(-2.0, 0.0, 0.0)),
+            sphere(2.0, Vec3::new(3.0, 1.0, -1.0)),
+        ]);
+        let bbox = scene.bounding_box().unwrap();
+        assert_eq!(bbox.min, Vec3::new(-3.0, -1.0, -3.0));
+        assert_eq!(bbox.max, Vec3::new(5.0, 3.0, 1.0));
+        for corner in bbox.corners() {
+            assert!(corner.x.is_finite() && corner.y.is_finite() && corner.z.is_finite());
+        }
+    }
+
+    #[test]
+    fn empty_scene_has_no_bounding_box() {
+        let mut scene = scene(Vec::new());
+        assert_eq!(scene.bounding_box(), None);
+        scene.fit_camera_dist();
+        assert_eq!(scene.camera_dist, 1.0);
+    }
+
+    #[test]
+    fn camera_dist_scales_with_the_scene() {
+        let spheres = |scale: Float| {
+            vec![
+                sphere(scale, Vec3::new(0.0, 0.0, 3.0) * scale),
+                sphere(scale, Vec3::new(2.0, 1.0, 4.0) * scale),
+            ]
+        };
+        let mut small = scene(spheres(1.0));
+        let mut large = scene(spheres(100.0));
+        small.fit_camera_dist();
+        large.fit_camera_dist();
+        assert!((large.camera_dist / small.camera_dist - 100.0).abs() < 1e-3);
+        // the field of view is unchanged
+        let fov = |scene: &Scene| scene.viewport.w / scene.camera_dist;
+        assert!((fov(&small) - 1.0).abs() < EPSILON);
+        assert!((fov(&large) - 1.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn roll_turns_right_and_up_about_forward() {
+        let level = camera();
+        let rolled = Camera {
+            z_rot: FRAC_PI_2,
+            ..camera()
+        };
+        let (view, level_view) = (rolled.view_matrix(), level.view_matrix());
+        assert_close(view.col3, level_view.col3);
+        assert_close(view.col1, Vec3::J);
+        assert_close(view.col2, -Vec3::I);
+
+        // with pitch and yaw, roll still leaves forward alone
+        let turned = Camera {
+            x_rot: 0.3,
+            y_rot: -0.8,
+            ..camera()
+        };
+        let turned_rolled = Camera {
+            z_rot: 0.5,
+            ..turned
+        };
+        let (a, b) = (turned.view_matrix(), turned_rolled.view_matrix());
+        assert_close(a.col3, b.col3);
+        assert_close(b.col1, a.col1.rotate_around(a.col3, 0.5));
+        assert_close(b.col2, a.col2.rotate_around(a.col3, 0.5));
+    }
+
+    #[test]
+    fn up_axis_rotates_the_camera_basis() {
+        let y_up = Camera {
+            x_rot: 0.2,
+            y_rot: 0.4,
+            ..camera()
+        };
+        // the default is the y-up convention the crate has always used
+        let view = y_up.view_matrix();
+        let expected = Mat3x3::y_rot_mat(0.4) * Mat3x3::x_rot_mat(0.2);
+        assert_close(view.col1, expected.col1);
+        assert_close(view.col2, expected.col2);
+        assert_close(view.col3, expected.col3);
+
+        let z_up = Camera {
+            up: Vec3::K,
+            ..camera()
+        };
+        let heading = z_up.heading();
+        assert_close(heading.col2, Vec3::K);
+        assert_close(heading.col1, Vec3::I);
+        assert_close(heading.col3, -Vec3::J);
+    }
+
+    #[test]
+    fn right_handed_worlds_mirror_z() {
+        let left = camera();
+        let right = Camera {
+            handedness: Handedness::Right,
+            ..camera()
+        };
+        assert_close(left.view_matrix().col3, Vec3::K);
+        assert_close(right.view_matrix().col3, -Vec3::K);
+        assert_close(right.view_matrix().col1, Vec3::I);
+        assert_close(right.view_matrix().col2, Vec3::J);
+    }
+
+    #[test]
+    fn dolly_zoom_moves_forward_and_narrows_the_view() {
+        let mut camera = camera();
+        let mut camera_dist = 1.0;
+        let target_dist = camera.dolly_zoom(&mut camera_dist, 10.0, 4.0);
+        assert_eq!(target_dist, 6.0);
+        assert_close(camera.position, Vec3::new(0.0, 0.0, 4.0));
+        assert!((camera_dist - 0.6).abs() < EPSILON);
+    }
+
+    #[test]
+    fn procedural_sky_blends_between_stops() {
+        let (zenith, horizon, nadir) = (Color::BLUE, Color::WHITE, Color::new(0, 0, 0, 0xff));
+        let sky = Background::ProceduralSky {
+            zenith,
+            horizon,
+            nadir,
+        };
+        assert_eq!(sky.color(Vec3::J), zenith);
+        assert_eq!(sky.color(Vec3::new(1.0, 0.0, 1.0)), horizon);
+        assert_eq!(sky.color(-Vec3::J), nadir);
+        let between = sky.color(Vec3::new(0.0, 1.0, 1.0));
+        assert_eq!(between.b, 0xff);
+        assert!(between.r > 0 && between.r < 0xff);
+        let lower = sky.color(Vec3::new(0.0, 1.0, 3.0));
+        assert!(lower.r > between.r);
+    }
+
+    #[test]
+    fn specular_exponents_are_clamped() {
+        assert_eq!(
+            Specularity::Specular(1e12).exponent(),
+            Some(Specularity::MAX_EXPONENT)
+        );
+        assert_eq!(
+            Specularity::Specular(0.0).exponent(),
+            Some(Specularity::MIN_EXPONENT)
+        );
+        assert_eq!(Specularity::Specular(100.0).exponent(), Some(100.0));
+        assert_eq!(Specularity::Specular(Float::NAN).exponent(), None);
+        assert_eq!(Specularity::Matte.exponent(), None);
+    }
+
+    #[test]
+    fn presets_have_their_documented_values() {
+        let plastic = Material::preset("plastic").unwrap();
+        assert_eq!(plastic.color, Color::WHITE);
+        assert!(matches!(plastic.specularity, Specularity::Specular(s) if s == 100.0));
+        assert_eq!(plastic.reflectiveness, 0.05);
+
+        let rubber = Material::preset("rubber").unwrap();
+        assert_eq!(rubber.color, Color::new(0x30, 0x30, 0x30, 0xff));
+        assert!(matches!(rubber.specularity, Specularity::Specular(s) if s == 10.0));
+        assert_eq!(rubber.reflectiveness, 0.0);
+
+        let metal = Material::preset("metal").unwrap();
+        let silver = Color::new(0xc0, 0xc0, 0xc0, 0xff);
+        assert!(matches!(
+            metal.specularity,
+            Specularity::Metallic { exponent, tint } if exponent == 500.0 && tint == silver
+        ));
+        assert_eq!(metal.reflectiveness, 0.5);
+
+        let mirror = Material::preset("mirror").unwrap();
+        assert!(matches!(mirror.specularity, Specularity::Specular(s) if s == 1000.0));
+        assert_eq!(mirror.reflectiveness, 0.9);
+
+        let glass = Material::preset("glass").unwrap();
+        assert!(matches!(glass.specularity, Specularity::Specular(s) if s == 1000.0));
+        assert_eq!(glass.reflectiveness, 0.1);
+        assert_eq!(glass.opacity, 0.2);
+    }
+
+    #[test]
+    fn every_listed_preset_exists() {
+        for name in Material::PRESETS {
+            assert!(Material::preset(name).is_some(), "{name}");
+        }
+        assert!(Material::preset("chrome").is_none());
+    }
+
+    #[test]
+    fn animated_lights_interpolate_their_keyframes() {
+        let mut light = PointLight::new(0.5, Vec3::ZERO);
+        light.animation = Some(LightAnimation {
+            keyframes: vec![(0.0, Vec3::ZERO), (2.0, Vec3::new(4.0, 0.0, 0.0))],
+            pulse: Some((0.5, 0.25)),
+        });
+        let at_start = light.at(0.0);
+        assert_eq!(at_start.position, Vec3::ZERO);
+        assert_eq!(at_start.intensity, 0.5);
+        let at_one = light.at(1.0);
+        assert_close(at_one.position, Vec3::new(2.0, 0.0, 0.0));
+        // a quarter of the way through the pulse, at its peak
+        assert!((at_one.intensity - 0.75).abs() < EPSILON);
+        assert_eq!(light.at(5.0).position, Vec3::new(4.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn still_lights_are_the_same_at_all_times() {
+        let light = PointLight::new(0.6, Vec3::new(1.0, 2.0, 3.0));
+        for time in [0.0, 1.5, 100.0] {
+            let at = light.at(time);
+            assert_eq!(at.position, light.position);
+            assert_eq!(at.intensity, light.intensity);
+        }
+    }
+
+    #[test]
+    fn keyframes_without_a_span_dont_divide_by_zero() {
+        let animation = LightAnimation {
+            keyframes: vec![(Float::NAN, Vec3::ZERO), (1.0, Vec3::I)],
+            pulse: None,
+        };
+        assert_eq!(animation.position_at(0.5), Some(Vec3::I));
+        assert_eq!(LightAnimation::default().position_at(0.5), None);
+    }
+
+    #[test]
+    fn grid_places_copies_at_the_spacing() {
+        let spacing = Vec3::new(1.0, 2.0, 3.0);
+        let copies = sphere(0.5, Vec3::new(10.0, 0.0, 0.0)).grid([3, 3, 3], spacing);
+        assert_eq!(copies.len(), 27);
+        for i in 0..3 {
+            for j in 0..3 {
+                for k in 0..3 {
+                    let expected = Vec3::new(10.0 + i as Float, 2.0 * j as Float, 3.0 * k as Float);
+                    assert!(copies.iter().any(|copy| copy.center == expected));
+                }
+            }
+        }
+        assert!(copies
+            .iter()
+            .all(|copy| copy.radius == 0.5 && copy.id.is_none()));
+    }
+
+    #[test]
+    fn merging_keeps_both_scenes_contents() {
+        let mut a = scene(vec![sphere(1.0, Vec3::ZERO)]);
+        a.lights.push(Light::Ambient(AmbientLight::new(0.2)));
+        let mut b = scene(vec![sphere(1.0, Vec3::ZERO)]);
+        b.background = Background::Solid(Color::BLUE);
+        b.lights.push(Light::Ambient(AmbientLight::new(0.3)));
+        a.merge(b);
+        // coinciding spheres are both kept
+        assert_eq!(a.spheres.len(), 2);
+        assert_eq!(a.lights.len(), 2);
+        assert_eq!(a.background, Background::Solid(Color::WHITE));
+    }
+
+    #[test]
+    fn mirrored_copies_get_their_own_handles() {
+        let mut scene = scene(Vec::new());
+        let id = scene.add_object(sphere(1.0, Vec3::new(0.0, 0.0, 2.0)));
+        scene.add_mirrored(Vec3::ZERO, Vec3::K);
+        assert_eq!(scene.spheres.len(), 2);
+        assert_eq!(scene.spheres[1].center, Vec3::new(0.0, 0.0, -2.0));
+        assert!(scene.spheres[1].id.is_some());
+        assert_ne!(scene.spheres[1].id, Some(id));
+    }
+
+    #[test]
+    fn removing_an_object_keeps_other_handles() {
+        let mut scene = scene(Vec::new());
+        let ids: Vec<ObjectId> = (0..3)
+            .map(|i| scene.add_object(sphere(1.0, Vec3::new(i as Float * 3.0, 0.0, 0.0))))
+            .collect();
+        let removed = scene.remove_object(ids[0]).unwrap();
+        assert_eq!(removed.id, Some(ids[0]));
+        assert_eq!(scene.index_of(ids[0]), None);
+        assert_eq!(scene.remove_object(ids[0]).map(|s| s.id), None);
+        for &id in &ids[1..] {
+            let index = scene.index_of(id).unwrap();
+            assert_eq!(scene.spheres[index].id, Some(id));
+        }
+        assert_eq!(scene.object_at(Vec3::ZERO), None);
+        assert_eq!(
+            scene.object_at(Vec3::new(3.0, 0.0, 0.0)),
+            scene.index_of(ids[1])
+        );
+    }
+
+    #[test]
+    fn named_materials_have_their_standard_refractive_index() {
+        assert_eq!(refractive_index_for("air"), Some(1.000293));
+        assert_eq!(refractive_index_for("water"), Some(1.333));
+        assert_eq!(refractive_index_for("glass"), Some(1.52));
+        assert_eq!(refractive_index_for("diamond"), Some(2.417));
+    }
+
+    #[test]
+    fn unknown_material_has_no_refractive_index() {
+        assert_eq!(refractive_index_for("unobtainium"), None);
+        assert_eq!(refractive_index_for("Glass"), None);
+    }
 }
@@ -1,2 +1,6 @@
 pub mod geometry;
+pub mod overlay;
 pub mod raytracer;
+pub mod sampling;
+pub mod scene_file;
+pub mod scene_json;
@@ -7,7 +7,9 @@ use error_iter::ErrorIter as _;
 use log::error;
 use pixels::{Pixels, SurfaceTexture};
 use raytracer::geometry::*;
+use raytracer::overlay::*;
 use raytracer::raytracer::*;
+use raytracer::scene_file::*;
 use winit::dpi::LogicalSize;
 use winit::event::{Event, VirtualKeyCode};
 use winit::event_loop::{ControlFlow, EventLoop};
@@ -16,8 +18,30 @@ use winit_input_helper::WinitInputHelper;
 
 const WIDTH: u32 = 640;
 const HEIGHT: u32 = 480;
-const CAMERA_MOV_STEP: f64 = 0.5;
-const CAMERA_ROT_STEP: f64 = 0.1;
+const CAMERA_MOV_STEP: Float = 0.5;
+const CAMERA_ROT_STEP: Float = 0.1;
+const OVERLAY_SCALE: u32 = 2;
+const HEADLIGHT_INTENSITY: Float = 0.6;
+// the grid toggled with G lies this far below the camera, with lines this
+// far apart
+const GRID_DEPTH: Float = 1.0;
+const GRID_SPACING: Float = 1.0;
+// the render time per frame automatic anti-aliasing aims for
+const TARGET_FRAME_MS: f64 = 1000.0 / 30.0;
+const MOVEMENT_KEYS: [VirtualKeyCode; 12] = [
+    VirtualKeyCode::W,
+    VirtualKeyCode::A,
+    VirtualKeyCode::S,
+    VirtualKeyCode::D,
+    VirtualKeyCode::Space,
+    VirtualKeyCode::LShift,
+    VirtualKeyCode::Up,
+    VirtualKeyCode::Down,
+    VirtualKeyCode::Left,
+    VirtualKeyCode::Right,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::E,
+];
 
 fn main() {
     #[cfg(target_arch = "wasm32")]
@@ -94,67 +118,100 @@ async fn run() {
         closure.forget();
     }
 
+    let mut settings = RenderSettings::new(WIDTH, HEIGHT);
+
     let mut pixels = {
         let window_size = window.inner_size();
         let surface_texture =
             SurfaceTexture::new(window_size.width, window_size.height, window.as_ref());
-        Pixels::new_async(WIDTH, HEIGHT, surface_texture)
+        Pixels::new_async(settings.width, settings.height, surface_texture)
             .await
             .expect("Pixels error")
     };
-    let scene = Scene {
-        spheres: vec![
-            Sphere::new(
-                1.0,
-                Vec3::new(0.0, -1.0, 3.0),
-                Color::new(0xb2, 0x0d, 0x30, 0xff),
-                Specularity::Specular(500.0),
-                0.0,
-            ),
-            Sphere::new(
-                1.0,
-                Vec3::new(2.0, 0.0, 4.0),
-                Color::new(0x3f, 0x84, 0xe5, 0xff),
-                Specularity::Specular(500.0),
-                0.5,
-            ),
-            Sphere::new(
-                1.0,
-                Vec3::new(-2.0, 0.0, 4.0),
-                Color::new(0x3f, 0x78, 0x4c, 0xff),
-                Specularity::Specular(10.0),
-                0.0,
-            ),
-            Sphere::new(
-                5000.0,
-                Vec3::new(0.0, -5001.0, 0.0),
-                Color::new(0xc1, 0x78, 0x17, 0xff),
-                Specularity::Specular(1000.0),
-                0.5,
-            ),
-        ],
-        bg_color: Color::WHITE,
-        canvas: Surface::new(WIDTH as f64, HEIGHT as f64),
-        viewport: Surface::new(2.0, 2.0 * HEIGHT as f64 / WIDTH as f64),
-        camera_dist: 1.0,
-        lights: vec![
-            Light::Ambient(AmbientLight::new(0.2)),
-            Light::Point(PointLight::new(0.6, Vec3::new(2.0, 1.0, 0.0))),
-            Light::Directional(DirectionalLight::new(0.2, Vec3::new(1.0, 4.0, 4.0))),
-        ],
-    };
+    // Scene description files may be passed as arguments, and are merged into
+    // the first
     let mut camera = Camera {
         position: Vec3::ZERO,
         y_rot: 0.0,
         x_rot: 0.0,
+        z_rot: 0.0,
+        up: Vec3::J,
+        handedness: Handedness::Left,
     };
+    let mut scenes = std::env::args()
+        .skip(1)
+        .map(|path| load_scene(&path).unwrap_or_else(|err| panic!("error loading {path}: {err}")));
+    let scene = match scenes.next() {
+        Some(mut scene) => {
+            scenes.for_each(|other| scene.merge(other));
+            // scene files can be of any scale, so start with all of it in view
+            scene.fit_camera_dist();
+            if let Some(bbox) = scene.bounding_box() {
+                camera = Camera::frame(bbox, &scene.viewport, scene.camera_dist, camera.handedness);
+            }
+            scene
+        }
+        None => default_scene(),
+    };
+
+    let mut show_overlay = false;
+    let mut show_crosshair = false;
+    // show transparency over a checkerboard rather than as the raw colors
+    let mut show_checkerboard = false;
+    // skip reflections while the camera is moving
+    let mut fast_navigation = false;
+    let mut last_frame_ms = now_ms();
+    // render coarsely while the view changes, then sharpen over a few frames
+    let mut refinement = Refinement::new();
+    // pick the anti-aliasing samples to fit frames within TARGET_FRAME_MS
+    let mut auto_samples = false;
 
-    scene.draw(pixels.frame_mut(), &camera);
+    scene.draw(pixels.frame_mut(), &camera, &settings);
 
     event_loop.run(move |event, _, control_flow| {
         // Draw the current frame
         if let Event::RedrawRequested(_) = event {
-            scene.draw(pixels.frame_mut(), &camera);
+            settings.block_size = refinement.block_size();
+            let draw_start_ms = now_ms();
+            let streamed = settings.samples > 1 && refinement.is_done();
+            if streamed {
+                // anti-aliased frames are slow, so show each tile as it is done
+                render_streaming(&scene, &camera, &settings, |tile| {
+                    let start = (tile.rows.start * settings.width * 4) as usize;
+                    let band = &mut pixels.frame_mut()[start..start + tile.pixels.len()];
+                    band.copy_from_slice(&tile.pixels);
+                    if show_checkerboard {
+                        draw_checkerboard(band, settings.width, tile.rows.start);
+                    }
+                    if let Err(err) = pixels.render() {
+                        log_error("pixels.render", err);
+                    }
+                });
+                draw_overlays(pixels.frame_mut(), &scene, &camera, &settings);
+            } else {
+                scene.draw(pixels.frame_mut(), &camera, &settings);
+            }
+            // coarse frames skip anti-aliasing, so only full ones are timed
+            if auto_samples && refinement.is_done() {
+                let draw_ms = now_ms() - draw_start_ms;
+                settings.samples = next_sample_count(settings.samples, draw_ms, TARGET_FRAME_MS);
+            }
+            refinement.advance();
+
+            let frame_ms = now_ms();
+            let fps = 1000.0 / (frame_ms - last_frame_ms).max(f64::EPSILON);
+            last_frame_ms = frame_ms;
+            // streamed tiles were composited as they arrived
+            if show_checkerboard && !streamed {
+                draw_checkerboard(pixels.frame_mut(), settings.width, 0);
+            }
+            if show_overlay {
+                draw_debug_overlay(pixels.frame_mut(), &settings, fps, &camera);
+            }
+            if show_crosshair {
+                draw_crosshair(pixels.frame_mut(), settings.width, settings.height);
+            }
+
             if let Err(err) = pixels.render() {
                 log_error("pixels.render", err);
                 *control_flow = ControlFlow::Exit;
@@ -166,38 +223,91 @@ async fn run() {
         if input.update(&event) {
             // TODO: this should probably be refactored and cleaned up
             if input.key_held(VirtualKeyCode::W) {
-                camera.position =
-                    camera.position + Mat3x3::y_rot_mat(camera.y_rot) * Vec3::K * CAMERA_MOV_STEP;
+                camera.position = camera.position + camera.heading() * Vec3::K * CAMERA_MOV_STEP;
             }
             if input.key_held(VirtualKeyCode::S) {
-                camera.position =
-                    camera.position - Mat3x3::y_rot_mat(camera.y_rot) * Vec3::K * CAMERA_MOV_STEP;
+                camera.position = camera.position - camera.heading() * Vec3::K * CAMERA_MOV_STEP;
             }
             if input.key_held(VirtualKeyCode::D) {
-                camera.position =
-                    camera.position + Mat3x3::y_rot_mat(camera.y_rot) * Vec3::I * CAMERA_MOV_STEP;
+                camera.position = camera.position + camera.heading() * Vec3::I * CAMERA_MOV_STEP;
             }
             if input.key_held(VirtualKeyCode::A) {
-                camera.position =
-                    camera.position - Mat3x3::y_rot_mat(camera.y_rot) * Vec3::I * CAMERA_MOV_STEP;
+                camera.position = camera.position - camera.heading() * Vec3::I * CAMERA_MOV_STEP;
             }
             if input.key_held(VirtualKeyCode::Space) {
-                camera.position.y = camera.position.y + CAMERA_MOV_STEP;
+                camera.position = camera.position + camera.up.normalize() * CAMERA_MOV_STEP;
             }
             if input.key_held(VirtualKeyCode::LShift) {
-                camera.position.y = camera.position.y - CAMERA_MOV_STEP;
+                camera.position = camera.position - camera.up.normalize() * CAMERA_MOV_STEP;
             }
             if input.key_held(VirtualKeyCode::Up) {
-                camera.x_rot = camera.x_rot - CAMERA_ROT_STEP;
+                camera.x_rot -= CAMERA_ROT_STEP;
             }
             if input.key_held(VirtualKeyCode::Down) {
-                camera.x_rot = camera.x_rot + CAMERA_ROT_STEP;
+                camera.x_rot += CAMERA_ROT_STEP;
             }
             if input.key_held(VirtualKeyCode::Left) {
-                camera.y_rot = camera.y_rot - CAMERA_ROT_STEP;
+                camera.y_rot -= CAMERA_ROT_STEP;
             }
             if input.key_held(VirtualKeyCode::Right) {
-                camera.y_rot = camera.y_rot + CAMERA_ROT_STEP;
+                camera.y_rot += CAMERA_ROT_STEP;
+            }
+            if input.key_held(VirtualKeyCode::Q) {
+                camera.z_rot += CAMERA_ROT_STEP;
+            }
+            if input.key_held(VirtualKeyCode::E) {
+                camera.z_rot -= CAMERA_ROT_STEP;
+            }
+            if input.key_pressed(VirtualKeyCode::F3) {
+                show_overlay = !show_overlay;
+            }
+            if input.key_pressed(VirtualKeyCode::K) {
+                show_checkerboard = !show_checkerboard;
+            }
+            if input.key_pressed(VirtualKeyCode::C) {
+                show_crosshair = !show_crosshair;
+            }
+            if input.key_pressed(VirtualKeyCode::B) {
+                settings.show_bounds = !settings.show_bounds;
+            }
+            if input.key_pressed(VirtualKeyCode::G) {
+                refinement.reset();
+                settings.grid = match settings.grid {
+                    Some(_) => None,
+                    None => Some(GridFloor::new(
+                        camera.position.y - GRID_DEPTH,
+                        GRID_SPACING,
+                        Color::new(0x80, 0x80, 0x80, 0xff),
+                    )),
+                };
+            }
+            if input.key_pressed(VirtualKeyCode::L) {
+                settings.show_lights = !settings.show_lights;
+            }
+            if input.key_pressed(VirtualKeyCode::H) {
+                refinement.reset();
+                settings.headlight = match settings.headlight {
+                    Some(_) => None,
+                    None => Some(HEADLIGHT_INTENSITY),
+                };
+            }
+            if input.key_pressed(VirtualKeyCode::Tab) {
+                refinement.reset();
+                settings.mode = next_mode(settings.mode);
+            }
+            if input.key_pressed(VirtualKeyCode::M) {
+                auto_samples = !auto_samples;
+                if !auto_samples {
+                    settings.samples = 1;
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::R) {
+                fast_navigation = !fast_navigation;
+            }
+            let moving = MOVEMENT_KEYS.iter().any(|&key| input.key_held(key));
+            settings.reflections = !(fast_navigation && moving);
+            if moving {
+                refinement.reset();
             }
 
             // Close events
@@ -208,6 +318,7 @@ async fn run() {
 
             // Resize the window
             if let Some(size) = input.window_resized() {
+                refinement.reset();
                 if let Err(err) = pixels.resize_surface(size.width, size.height) {
                     log_error("pixels.resize_surface", err);
                     *control_flow = ControlFlow::Exit;
@@ -221,30 +332,99 @@ async fn run() {
     });
 }
 
-fn log_error<E: std::error::Error + 'static>(method_name: &str, err: E) {
-    error!("{method_name}() failed: {err}");
-    for source in err.sources().skip(1) {
-        error!("  Caused by: {source}");
+fn default_scene() -> Scene {
+    Scene {
+        spheres: vec![
+            Sphere::new(
+                1.0,
+                Vec3::new(0.0, -1.0, 3.0),
+                Color::new(0xb2, 0x0d, 0x30, 0xff),
+                Specularity::Specular(500.0),
+                0.0,
+            ),
+            Sphere::new(
+                1.0,
+                Vec3::new(2.0, 0.0, 4.0),
+                Color::new(0x3f, 0x84, 0xe5, 0xff),
+                Specularity::Specular(500.0),
+                0.5,
+            ),
+            Sphere::new(
+                1.0,
+                Vec3::new(-2.0, 0.0, 4.0),
+                Color::new(0x3f, 0x78, 0x4c, 0xff),
+                Specularity::Specular(10.0),
+                0.0,
+            ),
+            Sphere::new(
+                5000.0,
+                Vec3::new(0.0, -5001.0, 0.0),
+                Color::new(0xc1, 0x78, 0x17, 0xff),
+                Specularity::Specular(1000.0),
+                0.5,
+            ),
+        ],
+        background: Background::Solid(Color::WHITE),
+        viewport: Surface::new(2.0, 2.0 * HEIGHT as Float / WIDTH as Float),
+        camera_dist: 1.0,
+        lights: vec![
+            Light::Ambient(AmbientLight::new(0.2)),
+            Light::Point(PointLight::new(0.6, Vec3::new(2.0, 1.0, 0.0))),
+            Light::Directional(DirectionalLight::new(0.2, Vec3::new(1.0, 4.0, 4.0))),
+        ],
+        specular_model: SpecularModel::Phong,
+        max_intensity: None,
+        sky_light: None,
     }
 }
 
-trait Drawable {
-    fn draw(&self, frame: &mut [u8], camera: &Camera);
-}
+/// milliseconds elapsed since an arbitrary fixed point, for frame timing
+fn now_ms() -> f64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        web_sys::window()
+            .and_then(|win| win.performance())
+            .map(|performance| performance.now())
+            .unwrap_or(0.0)
+    }
 
-impl Drawable for Scene {
-    fn draw(&self, frame: &mut [u8], camera: &Camera) {
-        for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
-            let x = (i % WIDTH as usize) as f64;
-            let y = (i / WIDTH as usize) as f64;
-            let cx = x - (WIDTH / 2) as f64;
-            let cy = (HEIGHT / 2) as f64 - y;
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use std::sync::OnceLock;
+        use std::time::Instant;
 
-            let dir = Mat3x3::y_rot_mat(camera.y_rot)
-                * (Mat3x3::x_rot_mat(camera.x_rot) * canvas_to_viewport(self, cx, cy));
-            let color = trace_ray(self, camera.position, dir, 1.0, f64::INFINITY, 3);
+        static START: OnceLock<Instant> = OnceLock::new();
+        START.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.0
+    }
+}
 
-            pixel.copy_from_slice(&color.as_u8_slice());
-        }
+/// draws the frame rate and camera position in the top left of the frame
+fn draw_debug_overlay(frame: &mut [u8], settings: &RenderSettings, fps: f64, camera: &Camera) {
+    let line_height = (GLYPH_HEIGHT + 1) * OVERLAY_SCALE;
+    let position = camera.position;
+    let lines = [
+        format!("FPS {fps:.0} AA {}", settings.samples),
+        format!(
+            "X {:.2} Y {:.2} Z {:.2}",
+            position.x, position.y, position.z
+        ),
+    ];
+    for (n, line) in lines.iter().enumerate() {
+        draw_text(
+            frame,
+            settings.width,
+            OVERLAY_SCALE,
+            OVERLAY_SCALE + n as u32 * line_height,
+            OVERLAY_SCALE,
+            line,
+            Color::new(0, 0, 0, 0xff),
+        );
+    }
+}
+
+fn log_error<E: std::error::Error + 'static>(method_name: &str, err: E) {
+    error!("{method_name}() failed: {err}");
+    for source in err.sources().skip(1) {
+        error!("  Caused by: {source}");
     }
 }
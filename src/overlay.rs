@@ -0,0 +1,492 @@
+use crate::geometry::*;
+use crate::raytracer::*;
+
+/// width of a glyph of the overlay font in font pixels
+pub const GLYPH_WIDTH: u32 = 3;
+/// height of a glyph of the overlay font in font pixels
+pub const GLYPH_HEIGHT: u32 = 5;
+
+/// the rows of the 3x5 glyph for c from top to bottom, where bit 2 is the
+/// leftmost pixel of a row. lowercase letters use the uppercase glyphs and
+/// unsupported characters are blank
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        _ => [0; 5],
+    }
+}
+
+/// sets the pixel at (x, y) of an RGBA frame that is width pixels wide,
+/// ignoring positions outside the frame
+fn set_pixel(frame: &mut [u8], width: u32, x: u32, y: u32, color: Color) {
+    if x >= width {
+        return;
+    }
+    let i = (y as usize * width as usize + x as usize) * 4;
+    if let Some(pixel) = frame.get_mut(i..i + 4) {
+        pixel.copy_from_slice(&color.as_u8_slice());
+    }
+}
+
+/// draws text into an RGBA frame that is width pixels wide with its top left
+/// corner at (x, y). each font pixel is drawn as a scale x scale block and
+/// only the glyph foreground is written, so the frame shows through
+pub fn draw_text(
+    frame: &mut [u8],
+    width: u32,
+    x: u32,
+    y: u32,
+    scale: u32,
+    text: &str,
+    color: Color,
+) {
+    for (n, c) in text.chars().enumerate() {
+        let glyph_x = x + n as u32 * (GLYPH_WIDTH + 1) * scale;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        set_pixel(
+                            frame,
+                            width,
+                            glyph_x + col * scale + dx,
+                            y + row as u32 * scale + dy,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// clips the segment from a to b to the rectangle [0, w] x [0, h] using the
+/// Liang-Barsky algorithm, returning None if no part of it is inside
+fn clip_line(
+    a: (Float, Float),
+    b: (Float, Float),
+    w: Float,
+    h: Float,
+) -> Option<((Float, Float), (Float, Float))> {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (mut t0, mut t1) = (0.0, 1.0);
+    for (p, q) in [(-dx, a.0), (dx, w - a.0), (-dy, a.1), (dy, h - a.1)] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let t = q / p;
+        if p < 0.0 {
+            t0 = Float::max(t0, t);
+        } else {
+            t1 = Float::min(t1, t);
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    Some((
+        (a.0 + t0 * dx, a.1 + t0 * dy),
+        (a.0 + t1 * dx, a.1 + t1 * dy),
+    ))
+}
+
+/// draws the line between two positions, in pixels from the top left corner,
+/// into an RGBA frame that is width pixels wide. parts of the line outside
+/// the frame are clipped
+pub fn draw_line(frame: &mut [u8], width: u32, a: (Float, Float), b: (Float, Float), color: Color) {
+    let height = (frame.len() / 4) as u32 / width;
+    let Some((a, b)) = clip_line(a, b, (width - 1) as Float, (height - 1) as Float) else {
+        return;
+    };
+    let steps = Float::max((b.0 - a.0).abs(), (b.1 - a.1).abs())
+        .ceil()
+        .max(1.0);
+    for i in 0..=steps as u32 {
+        let t = i as Float / steps;
+        let x = a.0 + t * (b.0 - a.0);
+        let y = a.1 + t * (b.1 - a.1);
+        set_pixel(frame, width, x.round() as u32, y.round() as u32, color);
+    }
+}
+
+/// how far each arm of the crosshair extends from the center pixel
+pub const CROSSHAIR_RADIUS: u32 = 6;
+
+/// draws a crosshair over the center pixel of an RGBA frame that is width x
+/// height pixels, along its center row and column. pixels are inverted
+/// rather than painted so it stays visible on any background
+pub fn draw_crosshair(frame: &mut [u8], width: u32, height: u32) {
+    let (cx, cy) = (width / 2, height / 2);
+    let mut invert = |x: u32, y: u32| {
+        let i = (y as usize * width as usize + x as usize) * 4;
+        if let Some(pixel) = frame.get_mut(i..i + 3) {
+            pixel.iter_mut().for_each(|c| *c = 0xff - *c);
+        }
+    };
+    for x in cx.saturating_sub(CROSSHAIR_RADIUS)..=(cx + CROSSHAIR_RADIUS).min(width - 1) {
+        invert(x, cy);
+    }
+    for y in cy.saturating_sub(CROSSHAIR_RADIUS)..=(cy + CROSSHAIR_RADIUS).min(height - 1) {
+        // the center pixel was already inverted with the row
+        if y != cy {
+            invert(cx, y);
+        }
+    }
+}
+
+/// distance in front of the camera that box edges are clipped to before
+/// projection, so edges passing behind the camera are still drawn
+const NEAR_CLIP: Float = 1e-3;
+
+/// draws the edges of every sphere's bounding box as seen by the camera
+pub fn draw_bounds(frame: &mut [u8], scene: &Scene, camera: &Camera, settings: &RenderSettings) {
+    for sphere in &scene.spheres {
+        let corners = sphere
+            .bounding_box()
+            .corners()
+            .map(|corner| world_to_camera(camera, corner));
+        for (i, j) in Aabb::edges() {
+            let (mut a, mut b) = (corners[i], corners[j]);
+            if a.z < NEAR_CLIP && b.z < NEAR_CLIP {
+                continue;
+            }
+            // move the endpoint behind the near plane onto it
+            if a.z < NEAR_CLIP {
+                a = a + (b - a) * ((NEAR_CLIP - a.z) / (b.z - a.z));
+            } else if b.z < NEAR_CLIP {
+                b = b + (a - b) * ((NEAR_CLIP - b.z) / (a.z - b.z));
+            }
+            if let (Some(a), Some(b)) = (
+                camera_to_frame(scene, settings, a),
+                camera_to_frame(scene, settings, b),
+            ) {
+                // draw_line rounds to the nearest pixel index, and the
+                // center of pixel i is at frame position i + 0.5
+                let to_index = |(x, y): (Float, Float)| (x - 0.5, y - 0.5);
+                draw_line(
+                    frame,
+                    settings.width,
+                    to_index(a),
+                    to_index(b),
+                    Color::GREEN,
+                );
+            }
+        }
+    }
+}
+
+/// half the width of the square marking a light
+pub const LIGHT_MARKER_RADIUS: u32 = 3;
+
+/// draws a filled square centered on the frame position (x, y), in pixels
+/// from the top left corner, reaching radius pixels out from the pixel it
+/// falls in. parts outside the frame are clipped
+pub fn draw_marker(
+    frame: &mut [u8],
+    width: u32,
+    (x, y): (Float, Float),
+    radius: u32,
+    color: Color,
+) {
+    let (x, y) = (x.floor() as i64, y.floor() as i64);
+    let radius = radius as i64;
+    for py in y - radius..=y + radius {
+        for px in x - radius..=x + radius {
+            if px >= 0 && py >= 0 {
+                set_pixel(frame, width, px as u32, py as u32, color);
+            }
+        }
+    }
+}
+
+/// marks the position of every point light in the scene as seen by the
+/// camera, without affecting the lighting
+pub fn draw_lights(frame: &mut [u8], scene: &Scene, camera: &Camera, settings: &RenderSettings) {
+    for light in &scene.lights {
+        let Light::Point(light) = light else {
+            continue;
+        };
+        let position = light.at(settings.time).position;
+        if let Some(pixel) = world_to_frame(scene, settings, camera, position) {
+            draw_marker(
+                frame,
+                settings.width,
+                pixel,
+                LIGHT_MARKER_RADIUS,
+                Color::new(0xff, 0xd7, 0x00, 0xff),
+            );
+        }
+    }
+}
+
+/// blends color over each pixel of an RGBA frame by the edge strength of
+/// the pixel from edges, as from render_edges, drawing ink outlines
+pub fn draw_outlines(frame: &mut [u8], edges: &[Float], color: Color) {
+    for (pixel, &strength) in frame.chunks_exact_mut(4).zip(edges) {
+        let under = Color::new(pixel[0], pixel[1], pixel[2], pixel[3]);
+        pixel.copy_from_slice(&under.lerp(color, strength).as_u8_slice());
+    }
+}
+
+/// the width in pixels of a square of the alpha checkerboard
+pub const CHECKER_SIZE: u32 = 8;
+
+/// composites each pixel of an RGBA frame that is width pixels wide over a
+/// gray checkerboard by its alpha, leaving the frame opaque, so transparent
+/// areas stand out when it is displayed. opaque pixels are unchanged but
+/// for their alpha. frame may be a band of whole rows starting at
+/// first_row, so a tile can be composited without touching the rest
+pub fn draw_checkerboard(frame: &mut [u8], width: u32, first_row: u32) {
+    for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (i as u32 % width, first_row + i as u32 / width);
+        let checker = match (x / CHECKER_SIZE + y / CHECKER_SIZE) % 2 {
+            0 => Color::new(0xcc, 0xcc, 0xcc, 0xff),
+            _ => Color::new(0x99, 0x99, 0x99, 0xff),
+        };
+        let color = Color::new(pixel[0], pixel[1], pixel[2], 0xff);
+        let alpha = pixel[3] as Float / 255.0;
+        pixel.copy_from_slice(&checker.lerp(color, alpha).as_u8_slice());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 64;
+
+    fn pixel(frame: &[u8], width: u32, x: u32, y: u32) -> Color {
+        let i = ((y * width + x) * 4) as usize;
+        Color::new(frame[i], frame[i + 1], frame[i + 2], frame[i + 3])
+    }
+
+    /// a scene with a unit sphere five units in front of the camera
+    fn scene() -> Scene {
+        Scene {
+            spheres: vec![Sphere::new(
+                1.0,
+                Vec3::new(0.0, 0.0, 5.0),
+                Color::WHITE,
+                Specularity::Matte,
+                0.0,
+            )],
+            background: Background::Solid(Color::WHITE),
+            viewport: Surface::new(1.0, 1.0),
+            camera_dist: 1.0,
+            lights: vec![Light::Point(PointLight::new(0.5, Vec3::new(0.0, 0.0, 5.0)))],
+            specular_model: SpecularModel::Phong,
+            max_intensity: None,
+            sky_light: None,
+        }
+    }
+
+    fn camera() -> Camera {
+        Camera {
+            position: Vec3::ZERO,
+            y_rot: 0.0,
+            x_rot: 0.0,
+            z_rot: 0.0,
+            up: Vec3::J,
+            handedness: Handedness::Left,
+        }
+    }
+
+    #[test]
+    fn text_sets_the_glyph_pixels() {
+        let (width, height) = (10, 7);
+        let mut frame = vec![0; (width * height * 4) as usize];
+        draw_text(&mut frame, width, 1, 1, 1, "HI", Color::WHITE);
+        // H then I, each three pixels wide with a one pixel gap
+        let expected = [
+            "..........",
+            ".#.#.###..",
+            ".#.#..#...",
+            ".###..#...",
+            ".#.#..#...",
+            ".#.#.###..",
+            "..........",
+        ];
+        for (y, row) in expected.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let color = pixel(&frame, width, x as u32, y as u32);
+                let set = color == Color::WHITE;
+                assert_eq!(set, c == '#', "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn text_is_clipped_to_the_frame() {
+        let (width, height) = (4, 4);
+        let mut frame = vec![0; (width * height * 4) as usize];
+        // most of the glyph lies past the right and bottom edges
+        draw_text(&mut frame, width, 2, 2, 2, "W", Color::WHITE);
+        assert_eq!(pixel(&frame, width, 3, 3), Color::WHITE);
+        assert_eq!(pixel(&frame, width, 1, 1), Color::new(0, 0, 0, 0));
+    }
+
+    #[test]
+    fn crosshair_inverts_only_the_center_row_and_column() {
+        let (width, height) = (21, 15);
+        let mut frame = vec![0; (width * height * 4) as usize];
+        draw_crosshair(&mut frame, width, height);
+        let radius = CROSSHAIR_RADIUS as i32;
+        for y in 0..height {
+            for x in 0..width {
+                let (dx, dy) = (x as i32 - 10, y as i32 - 7);
+                let on_arm = (dy == 0 && dx.abs() <= radius) || (dx == 0 && dy.abs() <= radius);
+                let expected = match on_arm {
+                    true => Color::new(0xff, 0xff, 0xff, 0),
+                    false => Color::new(0, 0, 0, 0),
+                };
+                assert_eq!(pixel(&frame, width, x, y), expected, "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn lines_are_clipped_to_the_frame() {
+        let (width, height) = (8, 8);
+        let mut frame = vec![0; (width * height * 4) as usize];
+        draw_line(&mut frame, width, (-10.0, 3.0), (20.0, 3.0), Color::RED);
+        for x in 0..width {
+            assert_eq!(pixel(&frame, width, x, 3), Color::RED);
+            assert_eq!(pixel(&frame, width, x, 4), Color::new(0, 0, 0, 0));
+        }
+        draw_line(&mut frame, width, (-5.0, -5.0), (-1.0, -1.0), Color::GREEN);
+        assert!(frame.chunks_exact(4).all(|p| p[1] == 0));
+    }
+
+    #[test]
+    fn bounds_are_drawn_through_the_projected_corners() {
+        let settings = RenderSettings::new(SIZE, SIZE);
+        let mut frame = vec![0; (SIZE * SIZE * 4) as usize];
+        draw_bounds(&mut frame, &scene(), &camera(), &settings);
+        // the near bottom left corner (-1, -1, 4) projects to (16, 48)
+        assert_eq!(pixel(&frame, SIZE, 16, 48), Color::GREEN);
+        assert_eq!(pixel(&frame, SIZE, 48, 16), Color::GREEN);
+        // no edge passes through the middle of the box
+        assert_eq!(pixel(&frame, SIZE, 32, 32), Color::new(0, 0, 0, 0));
+        let marked = frame.chunks_exact(4).filter(|p| p[1] == 0xff).count();
+        assert!(marked > 4 * 32, "{marked}");
+    }
+
+    #[test]
+    fn light_markers_cover_the_projected_position() {
+        let settings = RenderSettings::new(SIZE, SIZE);
+        let mut frame = vec![0; (SIZE * SIZE * 4) as usize];
+        draw_lights(&mut frame, &scene(), &camera(), &settings);
+        let gold = Color::new(0xff, 0xd7, 0x00, 0xff);
+        let r = LIGHT_MARKER_RADIUS;
+        for (x, y) in [(32, 32), (32 - r, 32 - r), (32 + r, 32 + r)] {
+            assert_eq!(pixel(&frame, SIZE, x, y), gold);
+        }
+        assert_eq!(pixel(&frame, SIZE, 33 + r, 32), Color::new(0, 0, 0, 0));
+        let marked = frame.chunks_exact(4).filter(|p| p[3] != 0).count();
+        assert_eq!(marked, ((2 * r + 1) * (2 * r + 1)) as usize);
+    }
+
+    #[test]
+    fn lights_behind_the_camera_are_not_marked() {
+        let mut scene = scene();
+        scene.lights = vec![Light::Point(PointLight::new(
+            0.5,
+            Vec3::new(0.0, 0.0, -5.0),
+        ))];
+        let settings = RenderSettings::new(SIZE, SIZE);
+        let mut frame = vec![0; (SIZE * SIZE * 4) as usize];
+        draw_lights(&mut frame, &scene, &camera(), &settings);
+        assert!(frame.iter().all(|&c| c == 0));
+    }
+
+    #[test]
+    fn checkerboard_shows_through_transparent_pixels() {
+        let width = 16;
+        let mut frame = vec![0; (width * 2 * 4) as usize];
+        let red = Color::new(0xff, 0, 0, 0xff);
+        frame[4..8].copy_from_slice(&red.as_u8_slice());
+        draw_checkerboard(&mut frame, width, 0);
+        assert_eq!(
+            pixel(&frame, width, 0, 0),
+            Color::new(0xcc, 0xcc, 0xcc, 0xff)
+        );
+        assert_eq!(pixel(&frame, width, 1, 0), red);
+        assert_eq!(
+            pixel(&frame, width, CHECKER_SIZE, 0),
+            Color::new(0x99, 0x99, 0x99, 0xff)
+        );
+    }
+
+    #[test]
+    fn checkerboard_of_a_band_continues_the_pattern() {
+        let width = 16;
+        let mut band = vec![0; (width * 4) as usize];
+        draw_checkerboard(&mut band, width, CHECKER_SIZE);
+        assert_eq!(
+            pixel(&band, width, 0, 0),
+            Color::new(0x99, 0x99, 0x99, 0xff)
+        );
+        assert_eq!(
+            pixel(&band, width, CHECKER_SIZE, 0),
+            Color::new(0xcc, 0xcc, 0xcc, 0xff)
+        );
+    }
+
+    #[test]
+    fn outlines_blend_by_edge_strength() {
+        let under = Color::new(0xff, 0xff, 0xff, 0xff);
+        let mut frame: Vec<u8> = [under; 3].iter().flat_map(|c| c.as_u8_slice()).collect();
+        let ink = Color::new(0, 0, 0, 0xff);
+        draw_outlines(&mut frame, &[0.0, 1.0, 0.5], ink);
+        assert_eq!(pixel(&frame, 3, 0, 0), under);
+        assert_eq!(pixel(&frame, 3, 1, 0), ink);
+        assert_eq!(pixel(&frame, 3, 2, 0), Color::new(0x80, 0x80, 0x80, 0xff));
+    }
+}
@@ -1,151 +1,2873 @@
-use std::ops::Add;
+use std::ops::{Add, Range};
 
 use crate::geometry::*;
+use crate::overlay::*;
+use crate::sampling::*;
 
-pub fn canvas_to_viewport(scene: &Scene, x: f64, y: f64) -> Vec3 {
-    let vw = scene.viewport.w;
-    let vh = scene.viewport.h;
-    let cw = scene.canvas.w;
-    let ch = scene.canvas.h;
-    Vec3::new(x * vw / cw, y * vh / ch, scene.camera_dist)
+/// how surfaces are shaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    /// lit by the scene's lights with the spheres' materials
+    #[default]
+    Shaded,
+    /// a uniform clay look that depends only on the surface's normal as seen
+    /// from the camera, ignoring lights, materials and reflections. useful
+    /// for inspecting geometry
+    Matcap,
+    /// lit like Shaded, but with the diffuse light from all lights together
+    /// quantized into settings.toon_bands flat bands for a cartoon look
+    Toon,
 }
 
-/// finds the sphere at the nearest intersection of the ray origin + dir * t
-/// within the given range of t
-fn closest_intersection(
-    scene: &Scene,
-    origin: Vec3,
-    dir: Vec3,
-    t_min: f64,
-    t_max: f64,
-) -> Option<(f64, &Sphere)> {
-    scene
-        .spheres
+impl RenderMode {
+    /// every mode, in the order next_mode cycles through them
+    pub const ALL: [RenderMode; 3] = [RenderMode::Shaded, RenderMode::Matcap, RenderMode::Toon];
+}
+
+/// the mode after current, wrapping around to the first after the last
+pub fn next_mode(current: RenderMode) -> RenderMode {
+    let i = RenderMode::ALL
         .iter()
-        // get the values of t at which the ray intersects the sphere
-        .map(|sphere| (intersect_ray_sphere(origin, dir, sphere), sphere))
-        // filter out values of t not in the given range
-        .filter(|((t1, t2), _)| *t1 >= t_min && *t1 <= t_max && *t2 >= t_min && *t2 <= t_max)
-        // get the closer value of t
-        .map(|((t1, t2), sphere)| (t1.min(t2), sphere))
-        // filter out t values at infinity
-        .filter(|(t, _)| *t < f64::INFINITY)
-        // find the sphere with the least t value
-        .min_by(|(t, _), (u, _)| t.total_cmp(u))
+        .position(|&mode| mode == current)
+        .unwrap_or(0);
+    RenderMode::ALL[(i + 1) % RenderMode::ALL.len()]
 }
 
-/// finds the color of the sphere at the nearest intersection of the ray
-/// origin + dir * t within the given range of t
-pub fn trace_ray(
-    scene: &Scene,
-    origin: Vec3,
-    dir: Vec3,
-    t_min: f64,
-    t_max: f64,
-    depth: u8,
-) -> Color {
-    if let Some((t, sphere)) = closest_intersection(scene, origin, dir, t_min, t_max) {
+/// runtime options controlling how a frame is rendered
+#[derive(Clone)]
+pub struct RenderSettings {
+    /// width of the rendered frame in pixels
+    pub width: u32,
+    /// height of the rendered frame in pixels
+    pub height: u32,
+    /// how surfaces are shaded
+    pub mode: RenderMode,
+    /// renders every sphere with this material instead of its own, for
+    /// comparing looks without editing the scene. geometry and visibility
+    /// are kept. None renders each sphere's own material
+    pub material_override: Option<Material>,
+    /// replaces pixels much brighter than those around them with the
+    /// median of their neighborhood, cleaning up noise from soft shadows
+    /// and other random sampling. None leaves the frame as rendered
+    pub firefly_filter: Option<FireflyFilter>,
+    /// the number of brightness levels a lit surface is divided into in
+    /// toon mode. higher counts approach smooth shading
+    pub toon_bands: u32,
+    /// draws ink outlines of this color over the rendered frame where depth
+    /// or surface direction changes sharply, as found by render_edges.
+    /// None draws no outlines
+    pub outlines: Option<Color>,
+    /// width of a pixel relative to its height. 1 is square pixels; other
+    /// values stretch the horizontal spread of rays for anamorphic output
+    pub pixel_aspect: Float,
+    /// anti-aliasing samples per pixel along each axis, so each pixel is
+    /// traced with samples x samples rays placed by the sampler; 1 disables
+    /// anti-aliasing
+    pub samples: u32,
+    /// how the anti-aliasing samples are placed within a pixel
+    pub sampler: Sampler,
+    /// seed for the random sampling, so renders are reproducible
+    pub seed: u64,
+    /// dithers lit colors as they are quantized, breaking up banding in
+    /// smooth shading. None truncates them
+    pub dither: Option<Dither>,
+    /// when set, anti-aliasing is adaptive: the frame is first rendered with
+    /// one ray per pixel, then only pixels differing from a neighbor by more
+    /// than this threshold in some channel are refined with the full grid of
+    /// samples
+    pub adaptive_threshold: Option<u8>,
+    /// when set, anti-aliasing is only applied at geometric edges: the frame
+    /// is first rendered with one ray per pixel, then only pixels whose
+    /// nearest sphere differs from a neighbor's are refined with the full
+    /// grid of samples. with adaptive_threshold also set, pixels selected by
+    /// either are refined
+    pub edge_aa: bool,
+    /// traces one ray through the center of each block_size x block_size
+    /// block of pixels and fills the block with its color, for fast previews.
+    /// 1 renders every pixel
+    pub block_size: u32,
+    /// draws the bounding box of every sphere over the rendered frame
+    pub show_bounds: bool,
+    /// marks the position of every point light over the rendered frame
+    pub show_lights: bool,
+    /// shadow rays cast towards random points on a point light with a
+    /// nonzero radius, whose average gives the soft shadow
+    pub shadow_samples: u32,
+    /// follows reflections off reflective spheres. with reflections off only
+    /// direct lighting is computed, which is much faster to render
+    pub reflections: bool,
+    /// the most times a ray may be reflected
+    pub max_depth: u8,
+    /// what a reflection cut off by max_depth sees instead of the scene,
+    /// blended in by the surface's reflectiveness so deep reflections don't
+    /// end in a seam. None uses the background in the reflected direction
+    pub depth_limit_color: Option<Color>,
+    /// reflections are no longer followed once the share of the pixel's
+    /// color they would contribute falls below this weight, so facing mirrors
+    /// fade out smoothly instead of stopping abruptly at max_depth
+    pub min_reflection_weight: Float,
+    /// reflections are not followed off spheres the camera sees farther
+    /// away than this distance, as they are barely visible there but cost
+    /// as much to trace. such spheres show only their own color, and
+    /// reflections off nearer ones are followed as usual. None follows
+    /// reflections at any distance
+    pub max_reflection_distance: Option<Float>,
+    /// intensity of a point light at the camera that moves with it, for
+    /// exploring dark scenes. None leaves the scene's lights as they are
+    pub headlight: Option<Float>,
+    /// composites translucent spheres over what is behind them in depth
+    /// order, each by its opacity, instead of letting rays through them at
+    /// random. exact but slower, as every sphere along a ray is shaded.
+    /// like the random pass-through, it applies to camera rays only
+    pub ordered_transparency: bool,
+    /// a grid of lines on a horizontal plane drawn into the frame, hidden
+    /// by spheres in front of it. None draws no grid
+    pub grid: Option<GridFloor>,
+    /// planes cutting away the geometry behind them, for cross-section views
+    pub clip_planes: Vec<ClipPlane>,
+    /// the most intensity a single light's specular highlight may add,
+    /// taming the flickering hotspots of very shiny surfaces under bright
+    /// lights. None leaves highlights unclamped
+    pub max_specular: Option<Float>,
+    /// strength in [0, 1] of the darkening of ambient and sky light where
+    /// other spheres are close to a surface, giving grounded objects soft
+    /// contact shadows. None disables it
+    pub contact_shadows: Option<Float>,
+    /// the time in seconds the frame shows, which animated lights follow
+    pub time: Float,
+    /// spheres whose projected diameter is below this many pixels are left
+    /// out of the frame, sparing the work and flicker of sub-pixel objects.
+    /// only camera rays skip them, so they still cast shadows and show in
+    /// reflections. 0 keeps every sphere
+    pub min_sphere_pixels: Float,
+}
+
+impl RenderSettings {
+    pub fn new(width: u32, height: u32) -> Self {
+        RenderSettings {
+            width,
+            height,
+            mode: RenderMode::Shaded,
+            material_override: None,
+            firefly_filter: None,
+            toon_bands: 3,
+            outlines: None,
+            pixel_aspect: 1.0,
+            samples: 1,
+            sampler: Sampler::Grid,
+            seed: 0,
+            dither: None,
+            adaptive_threshold: None,
+            edge_aa: false,
+            block_size: 1,
+            show_bounds: false,
+            show_lights: false,
+            shadow_samples: 16,
+            reflections: true,
+            max_depth: 3,
+            depth_limit_color: None,
+            min_reflection_weight: 1.0 / 255.0,
+            max_reflection_distance: None,
+            headlight: None,
+            ordered_transparency: false,
+            grid: None,
+            clip_planes: Vec::new(),
+            max_specular: None,
+            contact_shadows: None,
+            time: 0.0,
+            min_sphere_pixels: 0.0,
+        }
+    }
+}
+
+/// a plane that hides the geometry on its back side. where it cuts through a
+/// sphere the exposed interior is capped with a flat fill color
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClipPlane {
+    pub point: Vec3,
+    /// points towards the side that is kept
+    pub normal: Vec3,
+    pub fill: Color,
+}
+
+/// lines on the horizontal plane at y = height, spaced evenly along x and z
+/// through the origin, for judging scale and movement. only primary rays
+/// see it, so it is neither lit, reflected nor casting shadows, and the
+/// spaces between lines are transparent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridFloor {
+    pub height: Float,
+    /// distance between neighboring lines
+    pub spacing: Float,
+    pub color: Color,
+}
+
+impl GridFloor {
+    /// the width of a line as a fraction of the spacing
+    pub const LINE_WIDTH: Float = 0.04;
+
+    pub fn new(height: Float, spacing: Float, color: Color) -> Self {
+        GridFloor {
+            height,
+            spacing,
+            color,
+        }
+    }
+
+    /// whether the point on the plane (x, height, z) lies on a line
+    pub fn is_on_line(&self, x: Float, z: Float) -> bool {
+        let off_line = |u: Float| {
+            let u = u / self.spacing;
+            (u - u.round()).abs()
+        };
+        off_line(x).min(off_line(z)) <= Self::LINE_WIDTH / 2.0
+    }
+
+    /// the t within the given range at which the ray origin + dir * t
+    /// crosses the plane on a line, or None if it crosses between lines or
+    /// not at all
+    pub fn line_hit(&self, origin: Vec3, dir: Vec3, t_min: Float, t_max: Float) -> Option<Float> {
+        let t = (self.height - origin.y) / dir.y;
+        // rays parallel to the plane never cross it
+        if !(t.is_finite() && t >= t_min && t <= t_max) {
+            return None;
+        }
         let point = origin + t * dir;
-        let normal = (point - sphere.center).normalize();
-        let local_color =
-            sphere.color * compute_lighting(scene, point, normal, -dir, sphere.specularity);
-        if depth <= 0 || sphere.reflectiveness <= 0.0 {
-            return local_color;
+        self.is_on_line(point.x, point.z).then_some(t)
+    }
+}
+
+/// counts of the work done while rendering
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    pub primary_rays: u64,
+    pub reflection_rays: u64,
+    pub shadow_rays: u64,
+    /// ray-sphere intersection tests
+    pub intersection_tests: u64,
+    /// the most reflections followed from a primary ray
+    pub max_depth: u8,
+    /// pixels rendered
+    pub pixels: u64,
+    /// pixels where a reflection was cut off by settings.max_depth
+    pub pixels_at_depth_limit: u64,
+}
+
+impl RenderStats {
+    /// the fraction of pixels where a reflection was cut off by the depth
+    /// limit. a high fraction suggests raising settings.max_depth
+    pub fn depth_limit_fraction(&self) -> Float {
+        if self.pixels == 0 {
+            return 0.0;
+        }
+        self.pixels_at_depth_limit as Float / self.pixels as Float
+    }
+}
+
+/// the fraction of pixels reaching the depth limit above which rendering
+/// logs a warning
+const DEPTH_LIMIT_WARNING_FRACTION: Float = 0.1;
+
+/// the state shared while tracing the rays of a frame
+pub struct TraceContext<'a> {
+    pub scene: &'a Scene,
+    pub settings: &'a RenderSettings,
+    pub stats: RenderStats,
+    /// the random stream of the pixel being rendered
+    pub rng: Rng,
+    /// the dither offset of the pixel being rendered
+    pub dither_offset: Float,
+    /// whether a reflection of the pixel being rendered was cut off by the
+    /// depth limit
+    pub at_depth_limit: bool,
+    /// index of the sphere the last primary ray hit, None if it missed
+    pub primary_object: Option<usize>,
+    /// lights the scene in addition to its own lights, placed at the camera
+    /// from settings.headlight when rendering a frame
+    pub headlight: Option<PointLight>,
+    /// the rotation from camera space to world space of the camera the frame
+    /// is rendered from
+    pub view: Mat3x3,
+    /// the center and radius of a sphere around every sphere in the scene,
+    /// so rays missing the whole scene are rejected with a single test
+    bounds: Option<(Vec3, Float)>,
+    /// whether each sphere in the scene is culled from primary rays for
+    /// being too small on screen, set when rendering a frame
+    culled: Vec<bool>,
+    /// the rays being traced, from the primary ray down to the innermost,
+    /// when recording a ray tree. None when not recording
+    ray_stack: Option<Vec<RayTree>>,
+}
+
+impl<'a> TraceContext<'a> {
+    pub fn new(scene: &'a Scene, settings: &'a RenderSettings) -> Self {
+        TraceContext {
+            scene,
+            settings,
+            stats: RenderStats::default(),
+            rng: Rng::new(settings.seed),
+            dither_offset: 0.0,
+            at_depth_limit: false,
+            primary_object: None,
+            headlight: None,
+            view: Mat3x3::IDENTITY,
+            bounds: scene
+                .bounding_box()
+                .map(|bbox| (bbox.center(), (bbox.max - bbox.min).length() / 2.0)),
+            culled: vec![false; scene.spheres.len()],
+            ray_stack: None,
         }
+    }
 
-        let reflected_color = trace_ray(
-            &scene,
-            point,
-            reflect_ray(-dir, normal),
-            0.001,
-            f64::INFINITY,
-            depth - 1,
+    /// a context for rendering a frame of the scene from camera, with the
+    /// headlight, view and culling it sets up
+    pub fn for_frame(scene: &'a Scene, camera: &Camera, settings: &'a RenderSettings) -> Self {
+        let mut ctx = TraceContext::new(scene, settings);
+        ctx.headlight = settings
+            .headlight
+            .map(|intensity| PointLight::new(intensity, camera.position));
+        ctx.view = camera.view_matrix();
+        if settings.min_sphere_pixels > 0.0 {
+            ctx.culled = scene
+                .spheres
+                .iter()
+                .map(|sphere| {
+                    projected_diameter(scene, settings, camera, sphere) < settings.min_sphere_pixels
+                })
+                .collect();
+        }
+        ctx
+    }
+}
+
+pub trait Drawable {
+    fn draw(&self, frame: &mut [u8], camera: &Camera, settings: &RenderSettings);
+}
+
+impl Drawable for Scene {
+    fn draw(&self, frame: &mut [u8], camera: &Camera, settings: &RenderSettings) {
+        render_frame_with_stats(self, camera, settings, frame);
+    }
+}
+
+/// renders the scene into frame and reports the work it took. a scene with
+/// no spheres renders as just its background
+pub fn render_frame_with_stats(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    frame: &mut [u8],
+) -> RenderStats {
+    let mut ctx = TraceContext::for_frame(scene, camera, settings);
+    let at_depth_limit = render_rows(&mut ctx, camera, 0..settings.height, frame);
+
+    ctx.stats.pixels = at_depth_limit.len() as u64;
+    ctx.stats.pixels_at_depth_limit = at_depth_limit.iter().filter(|&&hit| hit).count() as u64;
+    if ctx.stats.depth_limit_fraction() > DEPTH_LIMIT_WARNING_FRACTION {
+        log::warn!(
+            "{:.0}% of pixels reached the reflection depth limit of {}; consider raising max_depth",
+            ctx.stats.depth_limit_fraction() * 100.0,
+            settings.max_depth,
         );
+    }
 
-        return local_color * (1.0 - sphere.reflectiveness)
-            + reflected_color * sphere.reflectiveness;
+    if let Some(filter) = settings.firefly_filter {
+        remove_fireflies(frame, settings.width, filter);
     }
-    scene.bg_color
+    draw_overlays(frame, scene, camera, settings);
+
+    ctx.stats
 }
 
-/// finds the values of t for which the ray origin + dir * t intersects with
-/// the sphere
-fn intersect_ray_sphere(origin: Vec3, dir: Vec3, sphere: &Sphere) -> (f64, f64) {
-    let r = sphere.radius;
-    let co = origin - sphere.center;
+/// draws the outlines, bounds and light markers that settings turn on over
+/// a whole rendered frame
+pub fn draw_overlays(frame: &mut [u8], scene: &Scene, camera: &Camera, settings: &RenderSettings) {
+    if let Some(color) = settings.outlines {
+        draw_outlines(frame, &render_edges(scene, camera, settings), color);
+    }
+    if settings.show_bounds {
+        draw_bounds(frame, scene, camera, settings);
+    }
+    if settings.show_lights {
+        draw_lights(frame, scene, camera, settings);
+    }
+}
 
-    let a = dir.dot(dir);
-    let b = 2.0 * co.dot(dir);
-    let c = co.dot(co) - r * r;
+/// finds fireflies, isolated pixels far brighter than those around them as
+/// left by random sampling, by comparing the luminance of each pixel with
+/// the median of the window around it. a pixel is only a firefly if it is
+/// the one pixel of its window that bright, so small bright features
+/// survive even on a dark background
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FireflyFilter {
+    /// how many times the median luminance of its window a pixel must
+    /// exceed to be a firefly
+    pub threshold: Float,
+    /// the width in pixels of the square window centered on each pixel,
+    /// rounded up to an odd number
+    pub window: u32,
+    /// how much brighter than the threshold, in luminance from 0 to 255, a
+    /// pixel must also be, so dim noise over a black median isn't a firefly
+    pub min_delta: Float,
+}
 
-    let discriminant = b * b - 4.0 * a * c;
-    if discriminant < 0.0 {
-        return (f64::INFINITY, f64::INFINITY);
+impl FireflyFilter {
+    pub fn new(threshold: Float, window: u32, min_delta: Float) -> Self {
+        FireflyFilter {
+            threshold,
+            window,
+            min_delta,
+        }
     }
+}
 
-    let t1 = (-b + f64::sqrt(discriminant)) / (2.0 * a);
-    let t2 = (-b - f64::sqrt(discriminant)) / (2.0 * a);
+/// the perceived brightness of the RGB channels of an RGBA pixel, from 0
+/// to 255, with rec. 709 weights
+fn luminance(pixel: &[u8]) -> Float {
+    0.2126 * pixel[0] as Float + 0.7152 * pixel[1] as Float + 0.0722 * pixel[2] as Float
+}
 
-    (t1, t2)
+/// replaces each firefly in an RGBA frame that is width pixels wide with
+/// the pixel of median luminance in its window. edges survive, as a pixel
+/// on the bright side of one has mostly bright pixels around it. windows
+/// are cut off at the borders of the frame
+pub fn remove_fireflies(frame: &mut [u8], width: u32, filter: FireflyFilter) {
+    let width = width as usize;
+    let height = frame.len() / 4 / width.max(1);
+    if width == 0 || height == 0 {
+        return;
+    }
+    let source = frame.to_vec();
+    let pixels: Vec<&[u8]> = source.chunks_exact(4).collect();
+    let lum: Vec<Float> = pixels.iter().map(|pixel| luminance(pixel)).collect();
+    let radius = filter.window as usize / 2;
+
+    for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (i % width, i / width);
+        let mut window: Vec<usize> = (y.saturating_sub(radius)..=(y + radius).min(height - 1))
+            .flat_map(|wy| {
+                (x.saturating_sub(radius)..=(x + radius).min(width - 1))
+                    .map(move |wx| wy * width + wx)
+            })
+            .collect();
+        window.sort_by(|&a, &b| lum[a].total_cmp(&lum[b]));
+        let median = window[window.len() / 2];
+        let limit = lum[median] * filter.threshold + filter.min_delta;
+        let outliers = window.iter().filter(|&&j| lum[j] > limit).count();
+        if lum[i] > limit && outliers == 1 {
+            pixel.copy_from_slice(pixels[median]);
+        }
+    }
 }
 
-/// reflect ray with respect to normal
-fn reflect_ray(ray: Vec3, normal: Vec3) -> Vec3 {
-    2.0 * normal * normal.dot(ray) - ray
+/// the number of rows in each tile render_streaming renders
+pub const TILE_ROWS: u32 = 16;
+
+/// a finished band of rows of a frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileResult {
+    pub rows: Range<u32>,
+    /// the RGBA pixels of the rows, in row order
+    pub pixels: Vec<u8>,
 }
 
-/// compute the lighting at the point with the given normal vector
-fn compute_lighting(
+/// renders a frame of the scene in tiles of TILE_ROWS rows from the top,
+/// passing each to on_tile as soon as it is done so partial results can be
+/// shown. together the tiles are exactly a whole frame rendered with the
+/// same settings, without the firefly filter and overlays, which
+/// remove_fireflies and draw_overlays apply
+pub fn render_streaming(
     scene: &Scene,
-    point: Vec3,
-    normal: Vec3,
-    point_to_camera: Vec3,
-    specularity: Specularity,
-) -> f64 {
-    scene
-        .lights
-        .iter()
-        .map(|light| {
-            let calculate_intensity = |intensity: f64, light_dir: Vec3, t_max: f64| {
-                // check for a shadow
-                if closest_intersection(scene, point, light_dir, 0.001, t_max).is_some() {
-                    return 0.0;
-                }
+    camera: &Camera,
+    settings: &RenderSettings,
+    mut on_tile: impl FnMut(TileResult),
+) {
+    let mut ctx = TraceContext::for_frame(scene, camera, settings);
+    for start in (0..settings.height).step_by(TILE_ROWS as usize) {
+        let rows = start..(start + TILE_ROWS).min(settings.height);
+        let mut pixels = vec![0; rows.len() * settings.width as usize * 4];
+        render_rows(&mut ctx, camera, rows.clone(), &mut pixels);
+        on_tile(TileResult { rows, pixels });
+    }
+}
 
-                let n_dot_l = normal.dot(light_dir);
-                let diffuse = if n_dot_l > 0.0 {
-                    n_dot_l / (normal.length() * light_dir.length())
-                } else {
-                    0.0
-                };
-                let specular = match specularity {
-                    Specularity::Specular(s) => {
-                        let reflect_dir = reflect_ray(light_dir, normal);
-                        let r_dot_v = reflect_dir.dot(point_to_camera);
-                        if r_dot_v > 0.0 {
-                            intensity
-                                * f64::powf(
-                                    r_dot_v / (reflect_dir.length() * point_to_camera.length()),
-                                    s,
-                                )
-                        } else {
-                            0.0
-                        }
-                    }
-                    Specularity::Matte => 0.0,
-                };
-                diffuse + specular
+/// renders rows of the frame into frame, which holds just those rows,
+/// returning whether each of their pixels reached the depth limit. the
+/// pixels are the same as in a whole frame rendered with ctx's settings
+fn render_rows(
+    ctx: &mut TraceContext,
+    camera: &Camera,
+    rows: Range<u32>,
+    frame: &mut [u8],
+) -> Vec<bool> {
+    let settings = ctx.settings;
+    let width = settings.width as usize;
+
+    // adaptive and edge anti-aliasing start from one ray per pixel and then
+    // only refine the pixels that contrast with their neighbors or lie on
+    // the edge of a sphere. blocks are a preview, so they are never refined
+    let block = settings.block_size.max(1);
+    let threshold = settings
+        .adaptive_threshold
+        .filter(|_| settings.samples > 1 && block == 1);
+    let edge_aa = settings.edge_aa && settings.samples > 1 && block == 1;
+    let selective = threshold.is_some() || edge_aa;
+    // rendering starts from the top of the block holding the first row, or
+    // a row early and ends a row late to give the rows all their neighbors
+    // for anti-aliasing
+    let start = match (block, selective) {
+        (1, true) => rows.start.saturating_sub(1),
+        (1, false) => rows.start,
+        _ => rows.start - rows.start % block,
+    };
+    let end = match selective {
+        true => (rows.end + 1).min(settings.height),
+        false => rows.end,
+    };
+    let position = |i: usize| ((i % width) as u32, start + (i / width) as u32);
+    let mut padded = vec![0; (end - start) as usize * width * 4];
+    let pixel_count = padded.len() / 4;
+    let mut at_depth_limit = vec![false; pixel_count];
+    let mut objects = vec![None; pixel_count];
+    for i in 0..pixel_count {
+        let (x, y) = position(i);
+        let (block_x, block_y) = (x - x % block, y - y % block);
+        if (x, y) != (block_x, block_y) {
+            // the rest of a block copies the pixel at its top left corner,
+            // which comes first in row order
+            let corner = (block_y - start) as usize * width + block_x as usize;
+            padded.copy_within(corner * 4..corner * 4 + 4, i * 4);
+            at_depth_limit[i] = at_depth_limit[corner];
+            objects[i] = objects[corner];
+            continue;
+        }
+        let color = if block > 1 {
+            begin_pixel(ctx, x, y);
+            let half_block = block as Float / 2.0;
+            trace_pixel(
+                ctx,
+                camera,
+                x as Float + half_block,
+                y as Float + half_block,
+            )
+        } else if selective {
+            begin_pixel(ctx, x, y);
+            trace_pixel(ctx, camera, x as Float + 0.5, y as Float + 0.5)
+        } else {
+            render_pixel(ctx, camera, x, y)
+        };
+        padded[i * 4..i * 4 + 4].copy_from_slice(&color.as_u8_slice());
+        at_depth_limit[i] = ctx.at_depth_limit;
+        objects[i] = ctx.primary_object;
+    }
+
+    if selective {
+        let mut mask = match threshold {
+            Some(threshold) => contrast_mask(&padded, settings.width, threshold),
+            None => vec![false; objects.len()],
+        };
+        if edge_aa {
+            let edges = edge_mask(&objects, settings.width);
+            mask.iter_mut()
+                .zip(edges)
+                .for_each(|(refine, edge)| *refine |= edge);
+        }
+        for (i, pixel) in padded.chunks_exact_mut(4).enumerate() {
+            let (x, y) = position(i);
+            if mask[i] && rows.contains(&y) {
+                let color = render_pixel(ctx, camera, x, y);
+                pixel.copy_from_slice(&color.as_u8_slice());
+                at_depth_limit[i] = ctx.at_depth_limit;
+            }
+        }
+    }
+
+    let skipped = (rows.start - start) as usize * width;
+    let len = rows.len() * width;
+    frame[..len * 4].copy_from_slice(&padded[skipped * 4..(skipped + len) * 4]);
+    at_depth_limit.drain(..skipped);
+    at_depth_limit.truncate(len);
+    at_depth_limit
+}
+
+/// renders row y of the frame, exactly as it appears in a whole frame
+/// rendered with the same settings, so a frame can be split across workers
+/// by rows. the overlays and firefly filter only apply to whole frames
+pub fn render_scanline(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    y: u32,
+) -> Vec<u8> {
+    let mut ctx = TraceContext::for_frame(scene, camera, settings);
+    let mut row = vec![0; settings.width as usize * 4];
+    render_rows(&mut ctx, camera, y..y + 1, &mut row);
+    row
+}
+
+/// a ray traced for a pixel, with the rays cast from where it hit, for
+/// seeing why a pixel has the color it does
+#[derive(Debug, Clone, PartialEq)]
+pub struct RayTree {
+    pub kind: RayKind,
+    pub origin: Vec3,
+    pub dir: Vec3,
+    /// where the ray first hits the scene, None if it hits nothing. for a
+    /// shadow ray, the sphere blocking the light
+    pub hit: Option<Hit>,
+    /// the color the ray brings back. None for shadow rays
+    pub color: Option<Color>,
+    /// the shadow and reflection rays cast from hit, in the order they were
+    /// traced
+    pub children: Vec<RayTree>,
+}
+
+impl RayTree {
+    fn new(kind: RayKind, origin: Vec3, dir: Vec3) -> Self {
+        RayTree {
+            kind,
+            origin,
+            dir,
+            hit: None,
+            color: None,
+            children: Vec::new(),
+        }
+    }
+}
+
+/// traces the primary ray through the center of the pixel at (x, y) as a
+/// frame rendered with the same settings would, and returns it with every
+/// ray traced for it. anti-aliasing samples other than the center are not
+/// traced
+pub fn trace_ray_tree(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    x: u32,
+    y: u32,
+) -> RayTree {
+    let mut ctx = TraceContext::for_frame(scene, camera, settings);
+    begin_pixel(&mut ctx, x, y);
+    ctx.ray_stack = Some(Vec::new());
+    trace_pixel(&mut ctx, camera, x as Float + 0.5, y as Float + 0.5);
+    let mut stack = ctx.ray_stack.unwrap_or_default();
+    stack.pop().expect("the primary ray is recorded")
+}
+
+/// starts recording a ray, if a ray tree is being recorded
+fn begin_record(ctx: &mut TraceContext, kind: RayKind, origin: Vec3, dir: Vec3) {
+    if let Some(stack) = &mut ctx.ray_stack {
+        stack.push(RayTree::new(kind, origin, dir));
+    }
+}
+
+/// records where the ray being recorded hit the scene
+fn record_hit(ctx: &mut TraceContext, hit: Option<&Intersection>) {
+    if let Some(ray) = ctx.ray_stack.as_mut().and_then(|stack| stack.last_mut()) {
+        ray.hit = hit.map(|hit| hit.to_hit(ray.origin, ray.dir));
+    }
+}
+
+/// finishes recording a ray with the color it brings back, adding it to
+/// the ray that cast it. the primary ray is left on the stack
+fn end_record(ctx: &mut TraceContext, color: Option<Color>) {
+    if let Some(stack) = &mut ctx.ray_stack {
+        let Some(mut ray) = stack.pop() else {
+            return;
+        };
+        ray.color = color;
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(ray),
+            None => stack.push(ray),
+        }
+    }
+}
+
+/// a stable 64-bit FNV-1a hash of a frame, for checking that renders are
+/// unchanged without storing whole images. it is the same on every platform
+/// and between runs
+pub fn frame_hash(frame: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    frame.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    })
+}
+
+/// renders a frame of the scene and returns its frame_hash
+pub fn render_and_hash(scene: &Scene, camera: &Camera, settings: &RenderSettings) -> u64 {
+    let mut frame = vec![0; (settings.width * settings.height * 4) as usize];
+    render_frame_with_stats(scene, camera, settings, &mut frame);
+    frame_hash(&frame)
+}
+
+/// the block size of progressive rendering, which renders coarsely right
+/// after the view changes and then refines with each frame while it stays
+/// still, halving the block size each time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Refinement {
+    block_size: u32,
+}
+
+impl Default for Refinement {
+    fn default() -> Self {
+        Refinement::new()
+    }
+}
+
+impl Refinement {
+    /// the block size a refinement starts from
+    pub const COARSEST: u32 = 8;
+
+    /// a refinement at the coarsest level
+    pub fn new() -> Self {
+        Refinement {
+            block_size: Self::COARSEST,
+        }
+    }
+
+    /// the block size to render the next frame with
+    pub fn block_size(self) -> u32 {
+        self.block_size
+    }
+
+    /// whether frames are rendered at full resolution
+    pub fn is_done(self) -> bool {
+        self.block_size == 1
+    }
+
+    /// moves to the next finer level after a frame is rendered
+    pub fn advance(&mut self) {
+        self.block_size = (self.block_size / 2).max(1);
+    }
+
+    /// goes back to the coarsest level, for when the view changes
+    pub fn reset(&mut self) {
+        *self = Refinement::new();
+    }
+}
+
+/// the fewest anti-aliasing samples per axis next_sample_count picks
+pub const MIN_AUTO_SAMPLES: u32 = 1;
+/// the most anti-aliasing samples per axis next_sample_count picks
+pub const MAX_AUTO_SAMPLES: u32 = 4;
+
+/// the anti-aliasing samples per axis to render the next frame with, given
+/// the current count and how long the last frame took to render, so frames
+/// stay within target_ms. lowers the count when over budget and raises it
+/// only when the frame would still fit with the extra samples, as the cost
+/// grows with the square of the count
+pub fn next_sample_count(current: u32, last_frame_ms: f64, target_ms: f64) -> u32 {
+    let current = current.clamp(MIN_AUTO_SAMPLES, MAX_AUTO_SAMPLES);
+    let growth = ((current + 1) as f64 / current as f64).powi(2);
+    if last_frame_ms > target_ms {
+        (current - 1).max(MIN_AUTO_SAMPLES)
+    } else if last_frame_ms * growth < target_ms {
+        (current + 1).min(MAX_AUTO_SAMPLES)
+    } else {
+        current
+    }
+}
+
+/// renders frame_count frames at frame_rate frames per second, starting at
+/// settings.time, passing each finished frame and its index to on_frame
+pub fn render_animation(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    frame_count: u32,
+    frame_rate: Float,
+    mut on_frame: impl FnMut(u32, &[u8]),
+) {
+    let mut settings = settings.clone();
+    let start = settings.time;
+    let mut frame = vec![0; (settings.width * settings.height * 4) as usize];
+    for i in 0..frame_count {
+        settings.time = start + i as Float / frame_rate;
+        render_frame_with_stats(scene, camera, &settings, &mut frame);
+        on_frame(i, &frame);
+    }
+}
+
+/// marks the pixels of an RGBA frame that is width pixels wide whose color
+/// differs from one of their four neighbors by more than threshold in the
+/// red, green or blue channel
+pub fn contrast_mask(frame: &[u8], width: u32, threshold: u8) -> Vec<bool> {
+    let pixels: Vec<&[u8]> = frame.chunks_exact(4).collect();
+    neighbor_mask(&pixels, width, |a, b| {
+        (0..3).any(|c| a[c].abs_diff(b[c]) > threshold)
+    })
+}
+
+/// marks the pixels of a frame that is width pixels wide whose sphere, as
+/// given by the index of the sphere each pixel shows or None for the
+/// background, differs from that of one of their four neighbors
+pub fn edge_mask(objects: &[Option<usize>], width: u32) -> Vec<bool> {
+    neighbor_mask(objects, width, |a, b| a != b)
+}
+
+/// marks the pixels of a frame that is width pixels wide for which differs
+/// holds with one of their four neighbors
+fn neighbor_mask<T>(pixels: &[T], width: u32, differs: impl Fn(&T, &T) -> bool) -> Vec<bool> {
+    let width = width as usize;
+    let height = pixels.len() / width;
+
+    (0..pixels.len())
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let pixel = &pixels[i];
+            (x > 0 && differs(pixel, &pixels[i - 1]))
+                || (x + 1 < width && differs(pixel, &pixels[i + 1]))
+                || (y > 0 && differs(pixel, &pixels[i - width]))
+                || (y + 1 < height && differs(pixel, &pixels[i + width]))
+        })
+        .collect()
+}
+
+/// the share of each pixel covered by each sphere, for compositing: one
+/// buffer per sphere in scene.spheres, holding a coverage in [0, 1] per
+/// pixel in row order. pixels are sampled like render_pixel samples them,
+/// so edges get fractional coverage when anti-aliasing is on
+pub fn render_masks(scene: &Scene, camera: &Camera, settings: &RenderSettings) -> Vec<Vec<f32>> {
+    let mut ctx = TraceContext::for_frame(scene, camera, settings);
+    let (width, height) = (settings.width, settings.height);
+    let mut masks = vec![vec![0.0; (width * height) as usize]; scene.spheres.len()];
+    for y in 0..height {
+        for x in 0..width {
+            begin_pixel(&mut ctx, x, y);
+            let offsets = if settings.samples <= 1 {
+                vec![(0.5, 0.5)]
+            } else {
+                settings.sampler.samples(settings.samples, &mut ctx.rng)
             };
+            let share = 1.0 / offsets.len() as f32;
+            for (dx, dy) in offsets {
+                let dir = primary_ray_dir(&ctx, camera, x as Float + dx, y as Float + dy);
+                let hit = closest_intersection(
+                    &mut ctx,
+                    camera.position,
+                    dir,
+                    1.0,
+                    Float::INFINITY,
+                    RayKind::Primary,
+                );
+                if let Some(hit) = hit {
+                    masks[hit.index][(y * width + x) as usize] += share;
+                }
+            }
+        }
+    }
+    masks
+}
 
-            match light {
-                Light::Ambient(light) => light.intensity,
-                Light::Point(light) => {
-                    calculate_intensity(light.intensity, light.position - point, 1.0)
+/// the depth and outward normal seen through the center of each pixel, in
+/// row order. depth is the camera space z of the nearest hit, which is
+/// infinite where the ray hits nothing, and the normal there is zero
+pub fn render_depth_normals(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+) -> (Vec<Float>, Vec<Vec3>) {
+    let mut ctx = TraceContext::for_frame(scene, camera, settings);
+    let (width, height) = (settings.width, settings.height);
+    let mut depths = Vec::with_capacity((width * height) as usize);
+    let mut normals = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            begin_pixel(&mut ctx, x, y);
+            let dir = primary_ray_dir(&ctx, camera, x as Float + 0.5, y as Float + 0.5);
+            let hit = closest_intersection(
+                &mut ctx,
+                camera.position,
+                dir,
+                1.0,
+                Float::INFINITY,
+                RayKind::Primary,
+            );
+            match hit {
+                Some(hit) => {
+                    let hit = hit.to_hit(camera.position, dir);
+                    depths.push(world_to_camera(camera, hit.point).z);
+                    normals.push(hit.normal);
                 }
-                Light::Directional(light) => {
-                    calculate_intensity(light.intensity, light.dir, f64::INFINITY)
+                None => {
+                    depths.push(Float::INFINITY);
+                    normals.push(Vec3::ZERO);
                 }
             }
+        }
+    }
+    (depths, normals)
+}
+
+/// the strength in [0, 1] of the edge at each pixel of a frame that is
+/// width pixels wide, from sobel gradients of its depth and normal buffers,
+/// for drawing ink outlines. depth steps are measured relative to the depth
+/// of the pixel, so a step doubling it counts as a full edge whatever the
+/// scale of the scene. pixels bordering the background are full edges, and
+/// flat or smoothly curving surfaces are close to 0
+pub fn edge_strength(depths: &[Float], normals: &[Vec3], width: u32) -> Vec<Float> {
+    assert_eq!(depths.len(), normals.len(), "one normal per depth");
+    let width = width as usize;
+    let height = depths.len() / width;
+    // neighbors past the border repeat the edge pixel
+    let at = |x: usize, y: usize, dx: isize, dy: isize| {
+        let x = x.saturating_add_signed(dx).min(width - 1);
+        let y = y.saturating_add_signed(dy).min(height - 1);
+        y * width + x
+    };
+    // (dx, dy, weight) of the sobel kernel for the gradient along x; the
+    // gradient along y uses it with dx and dy swapped
+    const SOBEL: [(isize, isize, Float); 6] = [
+        (-1, -1, -1.0),
+        (-1, 0, -2.0),
+        (-1, 1, -1.0),
+        (1, -1, 1.0),
+        (1, 0, 2.0),
+        (1, 1, 1.0),
+    ];
+
+    (0..depths.len())
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let window = SOBEL
+                .iter()
+                .flat_map(|&(dx, dy, _)| [at(x, y, dx, dy), at(x, y, dy, dx)]);
+            let misses = window.clone().filter(|&j| depths[j].is_infinite()).count();
+            if depths[i].is_infinite() || misses > 0 {
+                let all_miss = depths[i].is_infinite() && misses == 2 * SOBEL.len();
+                return if all_miss { 0.0 } else { 1.0 };
+            }
+
+            let (mut depth_x, mut depth_y) = (0.0, 0.0);
+            let (mut normal_x, mut normal_y) = (Vec3::ZERO, Vec3::ZERO);
+            for &(dx, dy, weight) in &SOBEL {
+                depth_x += weight * depths[at(x, y, dx, dy)];
+                depth_y += weight * depths[at(x, y, dy, dx)];
+                normal_x = normal_x + weight * normals[at(x, y, dx, dy)];
+                normal_y = normal_y + weight * normals[at(x, y, dy, dx)];
+            }
+            // a step from d to 2d gives a gradient of 4d, and opposite unit
+            // normals one of length 8
+            let depth_edge = depth_x.hypot(depth_y) / (4.0 * depths[i].abs());
+            let normal_edge = (normal_x.length().powi(2) + normal_y.length().powi(2)).sqrt() / 8.0;
+            depth_edge.max(normal_edge).min(1.0)
         })
-        .fold(0.0, Add::add)
+        .collect()
+}
+
+/// the edge strength of each pixel of a frame of the scene, in row order,
+/// which can be drawn over the rendered frame as ink outlines
+pub fn render_edges(scene: &Scene, camera: &Camera, settings: &RenderSettings) -> Vec<Float> {
+    let (depths, normals) = render_depth_normals(scene, camera, settings);
+    edge_strength(&depths, &normals, settings.width)
+}
+
+/// renders the pixel at (x, y). the result depends only on the scene,
+/// settings, camera and position, so pixels can be rendered independently
+/// and in any order
+pub fn render_pixel(ctx: &mut TraceContext, camera: &Camera, x: u32, y: u32) -> Color {
+    begin_pixel(ctx, x, y);
+    if ctx.settings.samples <= 1 {
+        return trace_pixel(ctx, camera, x as Float + 0.5, y as Float + 0.5);
+    }
+
+    let colors: Vec<Color> = ctx
+        .settings
+        .sampler
+        .samples(ctx.settings.samples, &mut ctx.rng)
+        .into_iter()
+        .map(|(dx, dy)| trace_pixel(ctx, camera, x as Float + dx, y as Float + dy))
+        .collect();
+    Color::average(&colors)
+}
+
+/// sets up the per-pixel state of ctx for rendering the pixel at (x, y)
+fn begin_pixel(ctx: &mut TraceContext, x: u32, y: u32) {
+    ctx.rng = Rng::for_pixel(ctx.settings.seed, x, y);
+    ctx.at_depth_limit = false;
+    ctx.dither_offset = match ctx.settings.dither {
+        Some(dither) => dither.offset(x, y, &mut ctx.rng),
+        None => 0.0,
+    };
+}
+
+/// finds the color seen through the frame position (x, y), measured in
+/// pixels from the top left corner. pixel (i, j) covers [i, i + 1) x
+/// [j, j + 1), so its center is at (i + 0.5, j + 0.5)
+fn trace_pixel(ctx: &mut TraceContext, camera: &Camera, x: Float, y: Float) -> Color {
+    let dir = primary_ray_dir(ctx, camera, x, y);
+    trace_ray(
+        ctx,
+        camera.position,
+        dir,
+        1.0,
+        Float::INFINITY,
+        ctx.settings.max_depth,
+        1.0,
+        RayKind::Primary,
+    )
+}
+
+/// the direction of the primary ray through the frame position (x, y),
+/// measured in pixels from the top left corner
+fn primary_ray_dir(ctx: &TraceContext, camera: &Camera, x: Float, y: Float) -> Vec3 {
+    let cx = x - ctx.settings.width as Float / 2.0;
+    let cy = ctx.settings.height as Float / 2.0 - y;
+    camera.view_matrix() * canvas_to_viewport(ctx.scene, ctx.settings, cx, cy)
+}
+
+pub fn canvas_to_viewport(scene: &Scene, settings: &RenderSettings, x: Float, y: Float) -> Vec3 {
+    let vw = scene.viewport.w;
+    let vh = scene.viewport.h;
+    let cw = settings.width as Float;
+    let ch = settings.height as Float;
+    Vec3::new(
+        x * vw / cw * settings.pixel_aspect,
+        y * vh / ch,
+        scene.camera_dist,
+    )
+}
+
+/// transforms a world space point into camera space, where the camera sits at
+/// the origin looking along +z with +y up
+pub fn world_to_camera(camera: &Camera, point: Vec3) -> Vec3 {
+    camera.view_matrix().transpose() * (point - camera.position)
+}
+
+/// projects a camera space point onto the frame, inverting the mapping used
+/// for primary rays, and returns its position in pixels from the top left
+/// corner. points at or behind the camera have no projection
+pub fn camera_to_frame(
+    scene: &Scene,
+    settings: &RenderSettings,
+    point: Vec3,
+) -> Option<(Float, Float)> {
+    if point.z <= 0.0 {
+        return None;
+    }
+    let vx = point.x * scene.camera_dist / point.z;
+    let vy = point.y * scene.camera_dist / point.z;
+    let cx = vx * settings.width as Float / scene.viewport.w / settings.pixel_aspect;
+    let cy = vy * settings.height as Float / scene.viewport.h;
+    Some((
+        cx + settings.width as Float / 2.0,
+        settings.height as Float / 2.0 - cy,
+    ))
+}
+
+/// the diameter in pixels of the sphere as seen from the camera, measured
+/// along the frame's height. infinite when the camera is inside the sphere
+pub fn projected_diameter(
+    scene: &Scene,
+    settings: &RenderSettings,
+    camera: &Camera,
+    sphere: &Sphere,
+) -> Float {
+    let distance = (sphere.center - camera.position).length();
+    if distance <= sphere.radius {
+        return Float::INFINITY;
+    }
+    // the angle the sphere subtends, projected onto the viewport plane
+    let half_angle = (sphere.radius / distance).asin();
+    let viewport_size = 2.0 * half_angle.tan() * scene.camera_dist;
+    viewport_size * settings.height as Float / scene.viewport.h
+}
+
+/// projects a world space point onto the frame as seen by the camera,
+/// returning its position in pixels from the top left corner. points at or
+/// behind the camera have no projection
+pub fn world_to_frame(
+    scene: &Scene,
+    settings: &RenderSettings,
+    camera: &Camera,
+    point: Vec3,
+) -> Option<(Float, Float)> {
+    camera_to_frame(scene, settings, world_to_camera(camera, point))
+}
+
+/// the nearest intersection of a ray with a sphere in the scene
+struct Intersection<'a> {
+    t: Float,
+    /// index of the sphere in scene.spheres
+    index: usize,
+    sphere: &'a Sphere,
+    /// set when the ray hits the cap where this plane cuts the sphere rather
+    /// than the sphere's surface
+    cap: Option<&'a ClipPlane>,
+}
+
+impl Intersection<'_> {
+    /// the hit of the ray origin + dir * t this intersection was found for
+    fn to_hit(&self, origin: Vec3, dir: Vec3) -> Hit {
+        let point = origin + self.t * dir;
+        Hit {
+            t: self.t,
+            point,
+            normal: match self.cap {
+                Some(plane) => -plane.normal.normalize(),
+                None => (point - self.sphere.center).normalize(),
+            },
+            sphere: self.index,
+            id: self.sphere.id,
+        }
+    }
+}
+
+/// finds the sphere at the nearest intersection of the ray origin + dir * t
+/// within the given range of t, ignoring spheres hidden from rays of the
+/// given kind. when several spheres are hit at exactly the same t, the one
+/// first in scene.spheres wins, so coincident surfaces render the same
+/// however the spheres are traversed
+fn closest_intersection<'a>(
+    ctx: &mut TraceContext<'a>,
+    origin: Vec3,
+    dir: Vec3,
+    t_min: Float,
+    t_max: Float,
+    kind: RayKind,
+) -> Option<Intersection<'a>> {
+    if !begin_ray(ctx, origin, dir, t_min, t_max, kind) {
+        return None;
+    }
+    let settings = ctx.settings;
+    let clip_planes = &settings.clip_planes;
+    let culled = &ctx.culled;
+    let stats = &mut ctx.stats;
+    let rng = &mut ctx.rng;
+
+    ctx.scene
+        .spheres
+        .iter()
+        .enumerate()
+        .filter(|&(index, sphere)| {
+            sphere.visible_to(kind) && !(kind == RayKind::Primary && culled[index])
+        })
+        .filter_map(|(index, sphere)| {
+            stats.intersection_tests += 1;
+            sphere_intersection(origin, dir, t_min, t_max, index, sphere, clip_planes)
+        })
+        // let camera rays through translucent spheres at random
+        .filter(|hit| {
+            let opacity = material_of(settings, hit.sphere).opacity;
+            kind != RayKind::Primary || opacity >= 1.0 || rng.next_float() < opacity
+        })
+        // find the sphere with the least t value, breaking ties by index
+        .min_by(|a, b| a.t.total_cmp(&b.t).then(a.index.cmp(&b.index)))
+}
+
+/// finds every sphere the ray origin + dir * t intersects within the given
+/// range of t, in no particular order, ignoring spheres hidden from rays of
+/// the given kind. unlike closest_intersection, translucent spheres are
+/// always hit
+fn intersections<'a>(
+    ctx: &mut TraceContext<'a>,
+    origin: Vec3,
+    dir: Vec3,
+    t_min: Float,
+    t_max: Float,
+    kind: RayKind,
+) -> Vec<Intersection<'a>> {
+    if !begin_ray(ctx, origin, dir, t_min, t_max, kind) {
+        return Vec::new();
+    }
+    let clip_planes = &ctx.settings.clip_planes;
+    let culled = &ctx.culled;
+    let stats = &mut ctx.stats;
+
+    ctx.scene
+        .spheres
+        .iter()
+        .enumerate()
+        .filter(|&(index, sphere)| {
+            sphere.visible_to(kind) && !(kind == RayKind::Primary && culled[index])
+        })
+        .filter_map(|(index, sphere)| {
+            stats.intersection_tests += 1;
+            sphere_intersection(origin, dir, t_min, t_max, index, sphere, clip_planes)
+        })
+        .collect()
+}
+
+/// counts a ray of the given kind and tests it against the bounds of the
+/// scene, returning false if it misses every sphere within the range of t
+fn begin_ray(
+    ctx: &mut TraceContext,
+    origin: Vec3,
+    dir: Vec3,
+    t_min: Float,
+    t_max: Float,
+    kind: RayKind,
+) -> bool {
+    let stats = &mut ctx.stats;
+    match kind {
+        RayKind::Primary => stats.primary_rays += 1,
+        RayKind::Reflection => stats.reflection_rays += 1,
+        RayKind::Shadow => stats.shadow_rays += 1,
+    }
+
+    let Some((center, radius)) = ctx.bounds else {
+        return false;
+    };
+    stats.intersection_tests += 1;
+    let (t1, t2) = intersect_ray_ball(origin, dir, center, radius);
+    !(t1 == Float::INFINITY || t1.max(t2) < t_min || t1.min(t2) > t_max)
+}
+
+/// the nearest point in the given range of t where the ray origin + dir * t
+/// enters the sphere or, if it starts inside the sphere, leaves it
+fn sphere_intersection<'a>(
+    origin: Vec3,
+    dir: Vec3,
+    t_min: Float,
+    t_max: Float,
+    index: usize,
+    sphere: &'a Sphere,
+    clip_planes: &'a [ClipPlane],
+) -> Option<Intersection<'a>> {
+    let ((t_enter, enter_cap), (t_exit, exit_cap)) = clip(origin, dir, sphere, clip_planes)?;
+    let (t, cap) = if t_enter >= t_min {
+        (t_enter, enter_cap)
+    } else {
+        (t_exit, exit_cap)
+    };
+    (t >= t_min && t <= t_max).then_some(Intersection {
+        t,
+        index,
+        sphere,
+        cap,
+    })
+}
+
+/// a value of t along a ray and the plane whose cap the ray crosses there,
+/// if any
+type Crossing<'a> = (Float, Option<&'a ClipPlane>);
+
+/// finds where the ray origin + dir * t enters and leaves the part of the
+/// sphere in front of every clip plane. None if the ray misses it
+fn clip<'a>(
+    origin: Vec3,
+    dir: Vec3,
+    sphere: &Sphere,
+    clip_planes: &'a [ClipPlane],
+) -> Option<(Crossing<'a>, Crossing<'a>)> {
+    let (t1, t2) = intersect_ray_sphere(origin, dir, sphere);
+    if t1 == Float::INFINITY {
+        return None;
+    }
+    let (mut t_enter, mut t_exit) = (t1.min(t2), t1.max(t2));
+    let (mut enter_cap, mut exit_cap) = (None, None);
+    // the sphere and the kept half-spaces are all convex, so the ray is
+    // inside their intersection over a single interval
+    for plane in clip_planes {
+        let dist = (origin - plane.point).dot(plane.normal);
+        let rate = dir.dot(plane.normal);
+        if rate == 0.0 {
+            if dist < 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let t_plane = -dist / rate;
+        if rate > 0.0 && t_plane > t_enter {
+            t_enter = t_plane;
+            enter_cap = Some(plane);
+        } else if rate < 0.0 && t_plane < t_exit {
+            t_exit = t_plane;
+            exit_cap = Some(plane);
+        }
+    }
+    (t_enter <= t_exit).then_some(((t_enter, enter_cap), (t_exit, exit_cap)))
+}
+
+/// where a ray first hits the scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub t: Float,
+    pub point: Vec3,
+    /// the outward surface normal at point
+    pub normal: Vec3,
+    /// index of the sphere hit in scene.spheres
+    pub sphere: usize,
+    /// the handle of the sphere hit, if it was added with Scene::add_object
+    pub id: Option<ObjectId>,
+}
+
+/// finds the nearest hit in front of each ray origins[i] + dirs[i] * t, for
+/// using the scene as a geometry query (picking, physics, lidar) rather than
+/// rendering it. spheres hidden from primary rays are ignored
+pub fn intersect_rays(scene: &Scene, origins: &[Vec3], dirs: &[Vec3]) -> Vec<Option<Hit>> {
+    assert_eq!(origins.len(), dirs.len(), "one direction per origin");
+    let settings = RenderSettings::new(0, 0);
+    let mut ctx = TraceContext::new(scene, &settings);
+    origins
+        .iter()
+        .zip(dirs)
+        .map(|(&origin, &dir)| {
+            let hit = closest_intersection(
+                &mut ctx,
+                origin,
+                dir,
+                0.0,
+                Float::INFINITY,
+                RayKind::Primary,
+            )?;
+            Some(hit.to_hit(origin, dir))
+        })
+        .collect()
+}
+
+/// finds the color of the sphere at the nearest intersection of the ray
+/// origin + dir * t within the given range of t, following at most depth
+/// reflections. weight is the share of the pixel's color the ray contributes
+#[allow(clippy::too_many_arguments)]
+pub fn trace_ray(
+    ctx: &mut TraceContext,
+    origin: Vec3,
+    dir: Vec3,
+    t_min: Float,
+    t_max: Float,
+    depth: u8,
+    weight: Float,
+    kind: RayKind,
+) -> Color {
+    let reflections_followed = ctx.settings.max_depth.saturating_sub(depth);
+    ctx.stats.max_depth = ctx.stats.max_depth.max(reflections_followed);
+
+    begin_record(ctx, kind, origin, dir);
+    // a grid line stops primary rays like an opaque surface
+    let grid_line = match ctx.settings.grid {
+        Some(grid) if kind == RayKind::Primary => grid
+            .line_hit(origin, dir, t_min, t_max)
+            .map(|t| (t, grid.color)),
+        _ => None,
+    };
+    let (t_max, grid_color) = match grid_line {
+        Some((t, color)) => (t, Some(color)),
+        None => (t_max, None),
+    };
+    let color = if ctx.settings.ordered_transparency && kind == RayKind::Primary {
+        let behind = grid_color.unwrap_or_else(|| ctx.scene.background.color(dir));
+        trace_layers(ctx, origin, dir, t_min, t_max, depth, weight, kind, behind)
+    } else {
+        let hit = closest_intersection(ctx, origin, dir, t_min, t_max, kind);
+        record_hit(ctx, hit.as_ref());
+        if kind == RayKind::Primary {
+            ctx.primary_object = hit.as_ref().map(|hit| hit.index);
+        }
+        match hit {
+            Some(hit) => shade(ctx, &hit, origin, dir, depth, weight, kind),
+            None => grid_color.unwrap_or_else(|| ctx.scene.background.color(dir)),
+        }
+    };
+    end_record(ctx, Some(color));
+    color
+}
+
+/// like trace_ray, but composites every sphere the ray passes through in
+/// front of the first opaque one over behind, the color seen past every
+/// sphere, nearest last, each by its opacity
+#[allow(clippy::too_many_arguments)]
+fn trace_layers(
+    ctx: &mut TraceContext,
+    origin: Vec3,
+    dir: Vec3,
+    t_min: Float,
+    t_max: Float,
+    depth: u8,
+    weight: Float,
+    kind: RayKind,
+    behind: Color,
+) -> Color {
+    let mut hits = intersections(ctx, origin, dir, t_min, t_max, kind);
+    hits.sort_by(|a, b| a.t.total_cmp(&b.t).then(a.index.cmp(&b.index)));
+    record_hit(ctx, hits.first());
+    if kind == RayKind::Primary {
+        ctx.primary_object = hits.first().map(|hit| hit.index);
+    }
+    let opacity = |hit: &Intersection| material_of(ctx.settings, hit.sphere).opacity;
+    let visible = match hits.iter().position(|hit| opacity(hit) >= 1.0) {
+        Some(opaque) => &hits[..=opaque],
+        None => &hits[..],
+    };
+    let layers: Vec<(Color, Float)> = visible
+        .iter()
+        .map(|hit| {
+            let color = shade(ctx, hit, origin, dir, depth, weight, kind);
+            (color, opacity(hit).clamp(0.0, 1.0))
+        })
+        .collect();
+    layers
+        .iter()
+        .rev()
+        .fold(behind, |behind, &(color, opacity)| {
+            behind.lerp(color, opacity)
+        })
+}
+
+/// the material the sphere is rendered with, which is the override from
+/// settings when one is set
+fn material_of(settings: &RenderSettings, sphere: &Sphere) -> Material {
+    settings
+        .material_override
+        .unwrap_or_else(|| sphere.material())
+}
+
+/// the color of the ray origin + dir * t of the given kind at the
+/// intersection hit, following at most depth reflections. weight is the
+/// share of the pixel's color the ray contributes
+fn shade(
+    ctx: &mut TraceContext,
+    hit: &Intersection,
+    origin: Vec3,
+    dir: Vec3,
+    depth: u8,
+    weight: Float,
+    kind: RayKind,
+) -> Color {
+    let &Intersection { t, sphere, cap, .. } = hit;
+    let material = material_of(ctx.settings, sphere);
+    let point = origin + t * dir;
+    // a cap is flat and matte, facing away from the kept side of its plane
+    let (normal, color, specularity, reflectiveness) = match cap {
+        Some(plane) => (
+            -plane.normal.normalize(),
+            plane.fill,
+            Specularity::Matte,
+            0.0,
+        ),
+        None => (
+            (point - sphere.center).normalize(),
+            material.color,
+            material.specularity,
+            material.reflectiveness,
+        ),
+    };
+    if ctx.settings.mode == RenderMode::Matcap {
+        return matcap(ctx.view.transpose() * normal);
+    }
+    let (color, reflection_tint) = match specularity.metal_tint() {
+        Some(tint) => (tint, tint),
+        None => (color, material.reflection_tint),
+    };
+    let ambient_visibility = match ctx.settings.contact_shadows {
+        Some(strength) => 1.0 - strength * contact_occlusion(ctx, point, normal),
+        None => 1.0,
+    };
+    let intensity = sanitize_intensity(
+        compute_lighting(ctx, point, normal, -dir, specularity, ambient_visibility),
+        ctx.scene.max_intensity,
+    );
+    let mut local_color = color.mul_dithered(intensity, ctx.dither_offset);
+    for light in &ctx.scene.lights {
+        if let Light::Ambient(light) = light {
+            if light.color != Color::WHITE {
+                let tinted = color.mul_componentwise(light.color);
+                local_color = local_color + tinted * (light.intensity * ambient_visibility);
+            }
+        }
+    }
+    if let Some(sky_light) = ctx.scene.sky_light {
+        let sky = ctx.scene.background.color(normal);
+        local_color = local_color + color.mul_componentwise(sky) * (sky_light * ambient_visibility);
+    }
+    let reflected_weight = weight * reflectiveness;
+    if !ctx.settings.reflections
+        || reflectiveness <= 0.0
+        || reflected_weight < ctx.settings.min_reflection_weight
+        || (kind == RayKind::Primary
+            && ctx
+                .settings
+                .max_reflection_distance
+                .is_some_and(|max| t * dir.length() > max))
+    {
+        return local_color;
+    }
+
+    let reflect_dir = reflect_ray(-dir, normal);
+    let reflected_color = if depth == 0 {
+        ctx.at_depth_limit = true;
+        ctx.settings
+            .depth_limit_color
+            .unwrap_or_else(|| ctx.scene.background.color(reflect_dir))
+    } else {
+        trace_ray(
+            ctx,
+            point,
+            reflect_dir,
+            secondary_t_min(point, reflect_dir),
+            Float::INFINITY,
+            depth - 1,
+            reflected_weight,
+            RayKind::Reflection,
+        )
+    };
+
+    local_color * (1.0 - reflectiveness)
+        + reflected_color.mul_componentwise(reflection_tint) * reflectiveness
+}
+
+/// the color of a clay sphere lit from the upper left of the camera at the
+/// point with the given camera space normal
+fn matcap(normal: Vec3) -> Color {
+    const CLAY: Color = Color {
+        r: 0xc8,
+        g: 0xb4,
+        b: 0xa0,
+        a: 0xff,
+    };
+    // towards the light, in camera space where the camera looks along +z
+    let light_dir = Vec3::new(-0.4, 0.5, -0.75).normalize();
+    let diffuse = normal.dot(light_dir).max(0.0);
+    // brightens edges facing away from the camera
+    let rim = (1.0 + normal.z).clamp(0.0, 1.0).powi(3);
+    CLAY * (0.25 + 0.75 * diffuse + 0.3 * rim)
+}
+
+/// distance a secondary ray from a point at unit scale travels before it can
+/// hit anything
+const RAY_EPSILON: Float = 0.001;
+
+/// finds the t_min for a secondary ray from origin along dir, so it doesn't
+/// hit the surface it starts on. the offset is a distance rather than a
+/// multiple of dir, and grows with the magnitude of origin to cover the
+/// floating-point error in hit points far from the world origin
+fn secondary_t_min(origin: Vec3, dir: Vec3) -> Float {
+    let scale = origin.x.abs().max(origin.y.abs()).max(origin.z.abs());
+    RAY_EPSILON * (1.0 + scale) / dir.length()
+}
+
+/// replaces a non-finite lighting intensity with 0 and clamps it to the range
+/// [0, max], so a stray NaN or infinity can't speckle the final image
+fn sanitize_intensity(intensity: Float, max: Option<Float>) -> Float {
+    if intensity.is_nan() {
+        return 0.0;
+    }
+    intensity.clamp(0.0, max.unwrap_or(Float::INFINITY))
+}
+
+/// finds the values of t for which the ray origin + dir * t intersects with
+/// the sphere
+fn intersect_ray_sphere(origin: Vec3, dir: Vec3, sphere: &Sphere) -> (Float, Float) {
+    intersect_ray_ball(origin, dir, sphere.center, sphere.radius)
+}
+
+/// finds the values of t for which the ray origin + dir * t intersects with
+/// the surface of the ball with the given center and radius
+fn intersect_ray_ball(origin: Vec3, dir: Vec3, center: Vec3, r: Float) -> (Float, Float) {
+    let co = origin - center;
+
+    let a = dir.dot(dir);
+    let b = 2.0 * co.dot(dir);
+    let c = co.dot(co) - r * r;
+
+    let mut discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        // a tangent ray can come out slightly negative from rounding, which
+        // would make silhouette pixels flicker between hit and miss
+        let rounding = 4.0 * Float::EPSILON * (b * b + (4.0 * a * c).abs());
+        if discriminant < -rounding {
+            return (Float::INFINITY, Float::INFINITY);
+        }
+        discriminant = 0.0;
+    }
+
+    let t1 = (-b + Float::sqrt(discriminant)) / (2.0 * a);
+    let t2 = (-b - Float::sqrt(discriminant)) / (2.0 * a);
+
+    (t1, t2)
+}
+
+/// the stretch of a ray origin + dir * t that lies inside a solid, from
+/// where it enters to where it leaves. the normals are the solid's outward
+/// surface normals, so normal_enter faces against the ray and normal_exit
+/// along it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    pub t_enter: Float,
+    pub t_exit: Float,
+    pub normal_enter: Vec3,
+    pub normal_exit: Vec3,
+}
+
+/// finds the interval of the ray origin + dir * t inside the sphere, or None
+/// if the ray misses it. t_enter may be negative when origin is inside the
+/// sphere, and both are negative when the sphere is behind the ray
+pub fn intersect_interval(origin: Vec3, dir: Vec3, sphere: &Sphere) -> Option<Interval> {
+    let (t1, t2) = intersect_ray_sphere(origin, dir, sphere);
+    if t1 == Float::INFINITY {
+        return None;
+    }
+    let (t_enter, t_exit) = (t1.min(t2), t1.max(t2));
+    let normal_at = |t: Float| (origin + t * dir - sphere.center).normalize();
+    Some(Interval {
+        t_enter,
+        t_exit,
+        normal_enter: normal_at(t_enter),
+        normal_exit: normal_at(t_exit),
+    })
+}
+
+/// the stretches of interval a that lie outside interval b, nearest first.
+/// where b carves into a, the carved surface is b's surface seen from
+/// inside, so its normal is b's flipped
+pub fn subtract_interval(a: Interval, b: Option<Interval>) -> Vec<Interval> {
+    let b = match b {
+        Some(b) if b.t_enter < a.t_exit && b.t_exit > a.t_enter => b,
+        _ => return vec![a],
+    };
+    let mut parts = Vec::with_capacity(2);
+    if b.t_enter > a.t_enter {
+        parts.push(Interval {
+            t_exit: b.t_enter,
+            normal_exit: -b.normal_enter,
+            ..a
+        });
+    }
+    if b.t_exit < a.t_exit {
+        parts.push(Interval {
+            t_enter: b.t_exit,
+            normal_enter: -b.normal_exit,
+            ..a
+        });
+    }
+    parts
+}
+
+/// a constructive solid geometry difference: the solid of sphere a with
+/// sphere b carved out of it, like a with a bite taken out
+pub struct Difference {
+    pub a: Sphere,
+    pub b: Sphere,
+}
+
+impl Difference {
+    /// finds the nearest interval of the ray origin + dir * t inside a but
+    /// not inside b that doesn't lie wholly behind origin, or None if the
+    /// ray misses the carved solid
+    pub fn intersect_interval(&self, origin: Vec3, dir: Vec3) -> Option<Interval> {
+        let a = intersect_interval(origin, dir, &self.a)?;
+        let b = intersect_interval(origin, dir, &self.b);
+        subtract_interval(a, b)
+            .into_iter()
+            .find(|part| part.t_exit >= 0.0)
+    }
+}
+
+/// reflect ray with respect to normal
+fn reflect_ray(ray: Vec3, normal: Vec3) -> Vec3 {
+    2.0 * normal * normal.dot(ray) - ray
+}
+
+/// whether anything casting shadows lies on the ray point + dir * t before
+/// t_max
+fn in_shadow(ctx: &mut TraceContext, point: Vec3, dir: Vec3, t_max: Float) -> bool {
+    begin_record(ctx, RayKind::Shadow, point, dir);
+    let hit = closest_intersection(
+        ctx,
+        point,
+        dir,
+        secondary_t_min(point, dir),
+        t_max,
+        RayKind::Shadow,
+    );
+    record_hit(ctx, hit.as_ref());
+    end_record(ctx, None);
+    hit.is_some()
+}
+
+/// the fraction of the point light visible from point. a light with no
+/// radius is either fully visible or hidden; otherwise shadow rays are cast
+/// towards random points on the light's surface
+fn point_light_visibility(ctx: &mut TraceContext, point: Vec3, light: &PointLight) -> Float {
+    if light.radius <= 0.0 {
+        return if in_shadow(ctx, point, light.position - point, 1.0) {
+            0.0
+        } else {
+            1.0
+        };
+    }
+
+    let samples = ctx.settings.shadow_samples.max(1);
+    let visible = (0..samples)
+        .filter(|_| {
+            let target = light.position + light.radius * sample_unit_sphere(&mut ctx.rng);
+            !in_shadow(ctx, point, target - point, 1.0)
+        })
+        .count();
+    visible as Float / samples as Float
+}
+
+/// the anisotropic highlight of Ashikhmin and Shirley without its Fresnel
+/// and normalization terms, which sharpens the Blinn-Phong highlight by
+/// exponents.0 along the surface in the direction of brush and by
+/// exponents.1 across it
+fn anisotropic_highlight(
+    normal: Vec3,
+    light_dir: Vec3,
+    point_to_camera: Vec3,
+    exponents: (Float, Float),
+    brush: Vec3,
+) -> Float {
+    let normal = normal.normalize();
+    let halfway = (light_dir.normalize() + point_to_camera.normalize()).normalize();
+    let cos = normal.dot(halfway);
+    if cos.is_nan() || cos <= 0.0 {
+        return 0.0;
+    }
+    let (u, v) = tangent_frame(normal, brush);
+    let sin_squared = 1.0 - cos * cos;
+    if sin_squared <= 0.0 {
+        return 1.0;
+    }
+    let exponent =
+        (exponents.0 * halfway.dot(u).powi(2) + exponents.1 * halfway.dot(v).powi(2)) / sin_squared;
+    Float::powf(cos.min(1.0), exponent)
+}
+
+/// unit tangents u and v perpendicular to the unit normal and each other,
+/// with u in the direction of dir projected onto the surface. when dir is
+/// parallel to the normal, u is an arbitrary tangent
+fn tangent_frame(normal: Vec3, dir: Vec3) -> (Vec3, Vec3) {
+    let projected = dir - normal * normal.dot(dir);
+    let u = if projected.length() > 1e-6 * dir.length() {
+        projected.normalize()
+    } else {
+        let other = if normal.x.abs() < 0.9 {
+            Vec3::I
+        } else {
+            Vec3::J
+        };
+        normal.cross(other).normalize()
+    };
+    (u, normal.cross(u))
+}
+
+/// roughly how much of the light arriving at the point with the given
+/// normal is blocked by nearby spheres, from 0 for none to 1 for all. each
+/// sphere blocks in proportion to the solid angle it covers, weighted by how
+/// squarely it faces the surface, so the sphere the point lies on blocks
+/// nothing
+fn contact_occlusion(ctx: &TraceContext, point: Vec3, normal: Vec3) -> Float {
+    let normal = normal.normalize();
+    ctx.scene
+        .spheres
+        .iter()
+        .filter(|sphere| sphere.cast_shadows)
+        .map(|sphere| {
+            let to_center = sphere.center - point;
+            let dist = to_center.length();
+            if dist <= sphere.radius {
+                return 0.0;
+            }
+            let cos = normal.dot(to_center) / dist;
+            cos.max(0.0) * (sphere.radius / dist).powi(2)
+        })
+        .fold(0.0, Add::add)
+        .min(1.0)
+}
+
+/// compute the lighting at the point with the given normal vector. ambient
+/// light is scaled by ambient_visibility
+fn compute_lighting(
+    ctx: &mut TraceContext,
+    point: Vec3,
+    normal: Vec3,
+    point_to_camera: Vec3,
+    specularity: Specularity,
+    ambient_visibility: Float,
+) -> Float {
+    let scene = ctx.scene;
+    let max_specular = ctx.settings.max_specular.unwrap_or(Float::INFINITY);
+    let bands = (ctx.settings.mode == RenderMode::Toon).then(|| ctx.settings.toon_bands.max(1));
+    // the diffuse and specular light reflected towards the camera from an
+    // unoccluded light
+    let reflected = |intensity: Float, light_dir: Vec3| -> (Float, Float) {
+        let n_dot_l = normal.dot(light_dir);
+        let metallic = specularity.metal_tint().is_some();
+        let diffuse = if n_dot_l > 0.0 && !metallic {
+            intensity * n_dot_l / (normal.length() * light_dir.length())
+        } else {
+            0.0
+        };
+        let specular = match (specularity, specularity.exponent()) {
+            (
+                Specularity::Anisotropic {
+                    exponent_u,
+                    exponent_v,
+                    brush,
+                    ..
+                },
+                _,
+            ) => {
+                let exponents = (
+                    exponent_u.clamp(Specularity::MIN_EXPONENT, Specularity::MAX_EXPONENT),
+                    exponent_v.clamp(Specularity::MIN_EXPONENT, Specularity::MAX_EXPONENT),
+                );
+                intensity
+                    * anisotropic_highlight(normal, light_dir, point_to_camera, exponents, brush)
+            }
+            (_, Some(s)) => {
+                let cos = match scene.specular_model {
+                    SpecularModel::Phong => {
+                        let reflect_dir = reflect_ray(light_dir, normal);
+                        reflect_dir.dot(point_to_camera)
+                            / (reflect_dir.length() * point_to_camera.length())
+                    }
+                    SpecularModel::BlinnPhong => {
+                        let halfway =
+                            (light_dir.normalize() + point_to_camera.normalize()).normalize();
+                        normal.dot(halfway) / normal.length()
+                    }
+                };
+                // a negative base with a fractional exponent makes powf NaN,
+                // and a NaN cos fails the comparison
+                if cos > 0.0 {
+                    intensity * Float::powf(cos.min(1.0), s)
+                } else {
+                    0.0
+                }
+            }
+            (_, None) => 0.0,
+        };
+        (diffuse, specular.min(max_specular))
+    };
+
+    let headlight = ctx.headlight.clone().map(Light::Point);
+    // the diffuse light, and the ambient and specular light, from each light
+    let (diffuse, rest) = scene
+        .lights
+        .iter()
+        .chain(&headlight)
+        .map(|light| match light {
+            // tinted ambient light is added to the surface color by trace_ray
+            Light::Ambient(light) if light.color != Color::WHITE => (0.0, 0.0),
+            Light::Ambient(light) => (0.0, light.intensity * ambient_visibility),
+            Light::Point(light) => {
+                let light = &light.at(ctx.settings.time);
+                let visibility = point_light_visibility(ctx, point, light);
+                if visibility > 0.0 {
+                    let (diffuse, specular) = reflected(light.intensity, light.position - point);
+                    (visibility * diffuse, visibility * specular)
+                } else {
+                    (0.0, 0.0)
+                }
+            }
+            Light::Directional(light) => {
+                if in_shadow(ctx, point, light.dir, Float::INFINITY) {
+                    (0.0, 0.0)
+                } else {
+                    reflected(light.intensity, light.dir)
+                }
+            }
+        })
+        .fold((0.0, 0.0), |(d0, r0), (d1, r1)| (d0 + d1, r0 + r1));
+    // the total diffuse light is banded, so any lit point gets at least the
+    // darkest band and unlit stays unlit
+    let diffuse = match bands {
+        Some(bands) => (diffuse * bands as Float).ceil() / bands as Float,
+        None => diffuse,
+    };
+    diffuse + rest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere(radius: Float, center: Vec3) -> Sphere {
+        Sphere::new(radius, center, Color::WHITE, Specularity::Matte, 0.0)
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!(a.approx_eq(b, 1e-4), "{a:?} != {b:?}");
+    }
+
+    /// what a surface with no light on it renders as. shading keeps the
+    /// sphere's alpha, unlike the transparent Color::BLACK
+    const UNLIT: Color = Color {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 0xff,
+    };
+
+    fn colored(radius: Float, center: Vec3, color: Color) -> Sphere {
+        Sphere::new(radius, center, color, Specularity::Matte, 0.0)
+    }
+
+    /// a unit sphere five units in front of the camera
+    fn ball(color: Color) -> Sphere {
+        colored(1.0, Vec3::new(0.0, 0.0, 5.0), color)
+    }
+
+    fn mirror(reflectiveness: Float) -> Sphere {
+        Sphere::new(
+            1.0,
+            Vec3::new(0.0, 0.0, 5.0),
+            Color::WHITE,
+            Specularity::Matte,
+            reflectiveness,
+        )
+    }
+
+    fn ambient(intensity: Float) -> Light {
+        Light::Ambient(AmbientLight::new(intensity))
+    }
+
+    fn point(intensity: Float, position: Vec3) -> Light {
+        Light::Point(PointLight::new(intensity, position))
+    }
+
+    fn scene(spheres: Vec<Sphere>, lights: Vec<Light>) -> Scene {
+        Scene {
+            spheres,
+            background: Background::Solid(Color::BLACK),
+            viewport: Surface::new(1.0, 1.0),
+            camera_dist: 1.0,
+            lights,
+            specular_model: SpecularModel::Phong,
+            max_intensity: None,
+            sky_light: None,
+        }
+    }
+
+    fn camera() -> Camera {
+        Camera {
+            position: Vec3::ZERO,
+            y_rot: 0.0,
+            x_rot: 0.0,
+            z_rot: 0.0,
+            up: Vec3::J,
+            handedness: Handedness::Left,
+        }
+    }
+
+    fn render(scene: &Scene, settings: &RenderSettings) -> (Vec<u8>, RenderStats) {
+        let mut frame = vec![0; (settings.width * settings.height * 4) as usize];
+        let stats = render_frame_with_stats(scene, &camera(), settings, &mut frame);
+        (frame, stats)
+    }
+
+    fn pixel(frame: &[u8], width: u32, x: u32, y: u32) -> Color {
+        let i = ((y * width + x) * 4) as usize;
+        Color::new(frame[i], frame[i + 1], frame[i + 2], frame[i + 3])
+    }
+
+    fn colors(frame: &[u8]) -> Vec<Color> {
+        frame
+            .chunks_exact(4)
+            .map(|p| Color::new(p[0], p[1], p[2], p[3]))
+            .collect()
+    }
+
+    /// the center pixel of a frame of odd size, whose ray runs straight
+    /// through the center of ball
+    fn center(scene: &Scene, settings: &RenderSettings) -> Color {
+        let (frame, _) = render(scene, settings);
+        pixel(
+            &frame,
+            settings.width,
+            settings.width / 2,
+            settings.height / 2,
+        )
+    }
+
+    /// the lighting at the origin of a surface facing +y, seen from the
+    /// direction to_camera
+    fn lighting(
+        scene: &Scene,
+        settings: &RenderSettings,
+        to_camera: Vec3,
+        specularity: Specularity,
+    ) -> Float {
+        let mut ctx = TraceContext::new(scene, settings);
+        compute_lighting(&mut ctx, Vec3::ZERO, Vec3::J, to_camera, specularity, 1.0)
+    }
+
+    #[test]
+    fn difference_shows_the_cavity_surface() {
+        // a unit sphere at z = 5 with a bite out of its near side
+        let difference = Difference {
+            a: sphere(1.0, Vec3::new(0.0, 0.0, 5.0)),
+            b: sphere(1.0, Vec3::new(0.0, 0.0, 4.0)),
+        };
+        let dir = Vec3::new(0.0, 0.0, 1.0);
+        let hit = difference
+            .intersect_interval(Vec3::new(0.0, 0.0, 0.0), dir)
+            .unwrap();
+        // the ray enters where it leaves b, on the inside of the bite
+        assert!((hit.t_enter - 5.0).abs() < 1e-4);
+        assert!((hit.t_exit - 6.0).abs() < 1e-4);
+        assert_close(hit.normal_enter, Vec3::new(0.0, 0.0, -1.0));
+        assert_close(hit.normal_exit, Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn difference_splits_a_when_b_is_inside_it() {
+        let a = intersect_interval(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            &sphere(2.0, Vec3::new(0.0, 0.0, 5.0)),
+        )
+        .unwrap();
+        let b = intersect_interval(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            &sphere(1.0, Vec3::new(0.0, 0.0, 5.0)),
+        );
+        let parts = subtract_interval(a, b);
+        assert_eq!(parts.len(), 2);
+        assert!((parts[0].t_exit - 4.0).abs() < 1e-4);
+        assert_close(parts[0].normal_exit, Vec3::new(0.0, 0.0, 1.0));
+        assert!((parts[1].t_enter - 6.0).abs() < 1e-4);
+        assert_close(parts[1].normal_enter, Vec3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn non_overlapping_difference_leaves_a_unchanged() {
+        let difference = Difference {
+            a: sphere(1.0, Vec3::new(0.0, 0.0, 5.0)),
+            b: sphere(1.0, Vec3::new(5.0, 0.0, 5.0)),
+        };
+        let (origin, dir) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(
+            difference.intersect_interval(origin, dir),
+            intersect_interval(origin, dir, &difference.a)
+        );
+    }
+
+    #[test]
+    fn difference_missing_a_is_empty() {
+        let difference = Difference {
+            a: sphere(1.0, Vec3::new(0.0, 0.0, 5.0)),
+            b: sphere(1.0, Vec3::new(0.0, 0.0, 4.0)),
+        };
+        let ray = (Vec3::new(0.0, 3.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(difference.intersect_interval(ray.0, ray.1), None);
+    }
+
+    #[test]
+    fn non_finite_intensity_is_sanitized() {
+        assert_eq!(sanitize_intensity(Float::NAN, None), 0.0);
+        assert_eq!(sanitize_intensity(Float::NEG_INFINITY, None), 0.0);
+        assert_eq!(sanitize_intensity(Float::INFINITY, Some(1.5)), 1.5);
+        assert_eq!(sanitize_intensity(0.5, Some(1.5)), 0.5);
+    }
+
+    #[test]
+    fn renders_at_any_resolution() {
+        let scene = scene(vec![ball(Color::RED)], vec![ambient(1.0)]);
+        for size in [9, 33] {
+            let settings = RenderSettings::new(size, size);
+            let (frame, _) = render(&scene, &settings);
+            assert_eq!(pixel(&frame, size, size / 2, size / 2), Color::RED);
+            assert_eq!(pixel(&frame, size, 0, 0), Color::BLACK);
+            assert_eq!(pixel(&frame, size, size - 1, size - 1), Color::BLACK);
+        }
+    }
+
+    #[test]
+    fn blinn_phong_highlight_is_broader() {
+        let mut phong = scene(vec![], vec![point(1.0, Vec3::new(1.0, 1.0, 0.0))]);
+        let settings = RenderSettings::new(1, 1);
+        let diffuse = lighting(&phong, &settings, Vec3::J, Specularity::Matte);
+        let spec = Specularity::Specular(10.0);
+        let phong_light = lighting(&phong, &settings, Vec3::J, spec);
+        phong.specular_model = SpecularModel::BlinnPhong;
+        let blinn = phong;
+        assert!(lighting(&blinn, &settings, Vec3::J, spec) > phong_light + 0.1);
+
+        // both peak when the camera is along the mirrored light
+        let mirrored = Vec3::new(-1.0, 1.0, 0.0);
+        assert!((lighting(&blinn, &settings, mirrored, spec) - diffuse - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn scanlines_and_tiles_match_the_full_frame() {
+        let scene = scene(
+            vec![
+                ball(Color::RED),
+                colored(1.0, Vec3::new(1.0, 1.0, 6.0), Color::GREEN),
+            ],
+            vec![ambient(0.2), point(0.8, Vec3::new(2.0, 2.0, 0.0))],
+        );
+        let mut settings = RenderSettings::new(20, 40);
+        settings.samples = 2;
+        settings.adaptive_threshold = Some(8);
+        settings.edge_aa = true;
+        let (frame, _) = render(&scene, &settings);
+
+        let rows: Vec<u8> = (0..settings.height)
+            .flat_map(|y| render_scanline(&scene, &camera(), &settings, y))
+            .collect();
+        assert!(rows == frame);
+
+        let mut tiles = Vec::new();
+        render_streaming(&scene, &camera(), &settings, |tile| tiles.push(tile));
+        assert_eq!(tiles.len(), 3);
+        assert_eq!(tiles[2].rows, 32..40);
+        let streamed: Vec<u8> = tiles.into_iter().flat_map(|tile| tile.pixels).collect();
+        assert!(streamed == frame);
+    }
+
+    #[test]
+    fn contrast_mask_marks_both_sides() {
+        let frame = [[0, 0, 0, 255], [200, 0, 0, 255], [205, 0, 0, 255]].concat();
+        assert_eq!(contrast_mask(&frame, 3, 10), [true, true, false]);
+        assert_eq!(contrast_mask(&frame, 1, 10), [true, true, false]);
+    }
+
+    #[test]
+    fn adaptive_sampling_traces_fewer_rays() {
+        let scene = scene(vec![ball(Color::RED)], vec![ambient(1.0)]);
+        let mut settings = RenderSettings::new(16, 16);
+        settings.samples = 4;
+        let (full, full_stats) = render(&scene, &settings);
+        settings.adaptive_threshold = Some(8);
+        let (adaptive, stats) = render(&scene, &settings);
+        assert!(stats.primary_rays < full_stats.primary_rays / 2);
+        for (x, y) in [(0, 0), (8, 8), (15, 15)] {
+            assert_eq!(pixel(&adaptive, 16, x, y), pixel(&full, 16, x, y));
+        }
+    }
+
+    #[test]
+    fn reflections_are_tinted() {
+        let mut sphere = mirror(1.0);
+        sphere.reflection_tint = Color::RED;
+        let mut scene = scene(vec![sphere], vec![]);
+        scene.background = Background::Solid(Color::WHITE);
+        assert_eq!(center(&scene, &RenderSettings::new(9, 9)), Color::RED);
+    }
+
+    #[test]
+    fn secondary_offset_grows_with_scale() {
+        let near = secondary_t_min(Vec3::new(0.0, 0.0, 1.0), Vec3::K);
+        let far = secondary_t_min(Vec3::new(0.0, 0.0, 1000.0), Vec3::K);
+        assert!((far / near - 500.5).abs() < 1e-2);
+        // the offset is a distance, whatever the length of dir
+        let long = secondary_t_min(Vec3::new(0.0, 0.0, 1.0), 2.0 * Vec3::K);
+        assert!((long * 2.0 - near).abs() < 1e-6);
+    }
+
+    #[test]
+    fn hidden_spheres_still_cast_shadows() {
+        let mut occluder = sphere(1.0, Vec3::new(0.0, 5.0, 0.0));
+        occluder.visible_primary = false;
+        let mut scene = scene(vec![occluder], vec![point(1.0, Vec3::new(0.0, 10.0, 0.0))]);
+        let settings = RenderSettings::new(1, 1);
+        assert_eq!(
+            lighting(&scene, &settings, Vec3::J, Specularity::Matte),
+            0.0
+        );
+        let up = intersect_rays(&scene, &[Vec3::ZERO], &[Vec3::J]);
+        assert_eq!(up, [None]);
+
+        scene.spheres[0].cast_shadows = false;
+        let lit = lighting(&scene, &settings, Vec3::J, Specularity::Matte);
+        assert!((lit - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn stats_count_the_rays() {
+        let scene = scene(
+            vec![ball(Color::RED)],
+            vec![point(1.0, Vec3::new(0.0, 5.0, 0.0))],
+        );
+        let (_, stats) = render(&scene, &RenderSettings::new(10, 10));
+        assert_eq!(stats.primary_rays, 100);
+        assert_eq!(stats.pixels, 100);
+        assert_eq!(stats.reflection_rays, 0);
+        assert_eq!(stats.max_depth, 0);
+        assert!(stats.shadow_rays > 0 && stats.shadow_rays < 100);
+        assert!(stats.intersection_tests > stats.primary_rays);
+    }
+
+    #[test]
+    fn light_radius_softens_shadows() {
+        let scene = scene(vec![sphere(1.0, Vec3::new(0.0, 5.0, 0.0))], vec![]);
+        let settings = RenderSettings::new(1, 1);
+        let mut ctx = TraceContext::new(&scene, &settings);
+        let mut light = PointLight::new(1.0, Vec3::new(0.0, 10.0, 0.0));
+        assert_eq!(point_light_visibility(&mut ctx, Vec3::ZERO, &light), 0.0);
+        let aside = Vec3::new(10.0, 0.0, 0.0);
+        assert_eq!(point_light_visibility(&mut ctx, aside, &light), 1.0);
+
+        light.radius = 3.0;
+        let penumbra = point_light_visibility(&mut ctx, Vec3::ZERO, &light);
+        assert!(penumbra > 0.0 && penumbra < 1.0, "{penumbra}");
+    }
+
+    #[test]
+    fn reflections_can_be_turned_off() {
+        let mut scene = scene(vec![mirror(1.0)], vec![]);
+        scene.background = Background::Solid(Color::WHITE);
+        let mut settings = RenderSettings::new(9, 9);
+        let (frame, stats) = render(&scene, &settings);
+        assert_eq!(pixel(&frame, 9, 4, 4), Color::WHITE);
+        assert!(stats.reflection_rays > 0);
+
+        settings.reflections = false;
+        let (frame, stats) = render(&scene, &settings);
+        assert_eq!(pixel(&frame, 9, 4, 4), UNLIT);
+        assert_eq!(stats.reflection_rays, 0);
+    }
+
+    #[test]
+    fn sky_light_lights_with_the_background() {
+        let sky = Color::new(0x40, 0x80, 0xc0, 0xff);
+        let mut scene = scene(vec![ball(Color::WHITE)], vec![]);
+        scene.background = Background::Solid(sky);
+        let settings = RenderSettings::new(9, 9);
+        assert_eq!(center(&scene, &settings), UNLIT);
+        scene.sky_light = Some(1.0);
+        assert_eq!(center(&scene, &settings), sky);
+    }
+
+    #[test]
+    fn interval_spans_the_sphere() {
+        let ball = sphere(1.0, Vec3::new(0.0, 0.0, 5.0));
+        let hit = intersect_interval(Vec3::ZERO, Vec3::K, &ball).unwrap();
+        assert!((hit.t_enter - 4.0).abs() < 1e-4 && (hit.t_exit - 6.0).abs() < 1e-4);
+        assert_close(hit.normal_enter, -Vec3::K);
+        assert_close(hit.normal_exit, Vec3::K);
+
+        let inside = intersect_interval(Vec3::new(0.0, 0.0, 5.0), Vec3::K, &ball).unwrap();
+        assert!((inside.t_enter + 1.0).abs() < 1e-4);
+        assert_eq!(intersect_interval(Vec3::ZERO, Vec3::J, &ball), None);
+    }
+
+    #[test]
+    fn rays_are_intersected_in_batches() {
+        let mut scene = scene(vec![], vec![]);
+        let id = scene.add_object(sphere(1.0, Vec3::new(0.0, 0.0, 5.0)));
+        let origins = [Vec3::ZERO, Vec3::ZERO];
+        let hits = intersect_rays(&scene, &origins, &[Vec3::K, Vec3::J]);
+        let hit = hits[0].unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-4);
+        assert_close(hit.point, Vec3::new(0.0, 0.0, 4.0));
+        assert_close(hit.normal, -Vec3::K);
+        assert_eq!((hit.sphere, hit.id), (0, Some(id)));
+        assert_eq!(hits[1], None);
+    }
+
+    #[test]
+    fn headlight_lights_a_dark_scene() {
+        let scene = scene(vec![ball(Color::RED)], vec![]);
+        let mut settings = RenderSettings::new(9, 9);
+        assert_eq!(center(&scene, &settings), UNLIT);
+        // a little over 1 so rounding can't dim the full red
+        settings.headlight = Some(1.01);
+        assert_eq!(center(&scene, &settings), Color::RED);
+    }
+
+    #[test]
+    fn clip_planes_cap_the_cut() {
+        let scene = scene(vec![ball(Color::RED)], vec![ambient(1.0)]);
+        let mut settings = RenderSettings::new(9, 9);
+        let cut = |normal| ClipPlane {
+            point: Vec3::new(0.0, 0.0, 5.0),
+            normal,
+            fill: Color::GREEN,
+        };
+        // keeping the far half shows the cap
+        settings.clip_planes = vec![cut(Vec3::K)];
+        assert_eq!(center(&scene, &settings), Color::GREEN);
+        settings.clip_planes = vec![cut(-Vec3::K)];
+        assert_eq!(center(&scene, &settings), Color::RED);
+    }
+
+    #[test]
+    fn center_ray_looks_forward_for_odd_sizes() {
+        let scene = scene(vec![], vec![]);
+        let settings = RenderSettings::new(5, 3);
+        let ctx = TraceContext::for_frame(&scene, &camera(), &settings);
+        let dir = primary_ray_dir(&ctx, &camera(), 2.5, 1.5);
+        assert_close(dir.normalize(), Vec3::K);
+    }
+
+    #[test]
+    fn dolly_zoom_keeps_the_target_in_place() {
+        let mut scene = scene(vec![], vec![]);
+        let settings = RenderSettings::new(16, 16);
+        let mut camera = camera();
+        let (target, near) = (Vec3::new(0.3, 0.2, 5.0), Vec3::new(0.3, 0.2, 4.0));
+        let before = world_to_frame(&scene, &settings, &camera, target).unwrap();
+        let near_before = world_to_frame(&scene, &settings, &camera, near).unwrap();
+
+        let remaining = camera.dolly_zoom(&mut scene.camera_dist, 5.0, 2.0);
+        assert_eq!(remaining, 3.0);
+        let after = world_to_frame(&scene, &settings, &camera, target).unwrap();
+        assert!((after.0 - before.0).abs() < 1e-3 && (after.1 - before.1).abs() < 1e-3);
+        let near_after = world_to_frame(&scene, &settings, &camera, near).unwrap();
+        assert!((near_after.0 - near_before.0).abs() > 0.1);
+    }
+
+    #[test]
+    fn rays_missing_the_bounds_skip_the_spheres() {
+        let spheres = (0..10)
+            .map(|i| sphere(0.5, Vec3::new(i as Float, 0.0, 5.0)))
+            .collect();
+        let scene = scene(spheres, vec![]);
+        let settings = RenderSettings::new(1, 1);
+        let mut ctx = TraceContext::new(&scene, &settings);
+        let miss = closest_intersection(
+            &mut ctx,
+            Vec3::ZERO,
+            -Vec3::K,
+            0.0,
+            Float::INFINITY,
+            RayKind::Reflection,
+        );
+        assert!(miss.is_none());
+        assert_eq!(ctx.stats.intersection_tests, 1);
+
+        let hit = closest_intersection(
+            &mut ctx,
+            Vec3::ZERO,
+            Vec3::K,
+            0.0,
+            Float::INFINITY,
+            RayKind::Reflection,
+        );
+        assert_eq!(hit.map(|hit| hit.index), Some(0));
+        assert_eq!(ctx.stats.intersection_tests, 12);
+    }
+
+    #[test]
+    fn dither_spreads_quantized_colors() {
+        let gray = Color::new(100, 100, 100, 0xff);
+        let scene = scene(
+            vec![colored(2.0, Vec3::new(0.0, 0.0, 5.0), gray)],
+            vec![ambient(0.505)],
+        );
+        let mut settings = RenderSettings::new(16, 16);
+        let levels = |settings: &RenderSettings| {
+            let (frame, _) = render(&scene, settings);
+            let mut reds: Vec<u8> = colors(&frame)
+                .iter()
+                .map(|c| c.r)
+                .filter(|&r| r > 0)
+                .collect();
+            reds.sort();
+            reds.dedup();
+            reds
+        };
+        assert_eq!(levels(&settings), [50]);
+        settings.dither = Some(Dither::Ordered);
+        assert_eq!(levels(&settings), [50, 51]);
+    }
+
+    #[test]
+    fn faint_reflections_are_not_followed() {
+        let scene = scene(vec![mirror(0.5)], vec![]);
+        let mut settings = RenderSettings::new(9, 9);
+        assert!(render(&scene, &settings).1.reflection_rays > 0);
+        settings.min_reflection_weight = 0.6;
+        assert_eq!(render(&scene, &settings).1.reflection_rays, 0);
+    }
+
+    #[test]
+    fn pixel_aspect_stretches_rays_and_projection() {
+        let scene = scene(vec![], vec![]);
+        let mut settings = RenderSettings::new(8, 4);
+        let square = canvas_to_viewport(&scene, &settings, 1.0, 1.0);
+        settings.pixel_aspect = 2.0;
+        let wide = canvas_to_viewport(&scene, &settings, 1.0, 1.0);
+        assert_eq!((wide.x, wide.y), (2.0 * square.x, square.y));
+
+        let ctx = TraceContext::for_frame(&scene, &camera(), &settings);
+        let dir = primary_ray_dir(&ctx, &camera(), 3.5, 1.5);
+        let (x, y) = world_to_frame(&scene, &settings, &camera(), 3.0 * dir).unwrap();
+        assert!((x - 3.5).abs() < 1e-4 && (y - 1.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn depth_limit_fraction_counts_cut_off_pixels() {
+        let stats = RenderStats {
+            pixels: 10,
+            pixels_at_depth_limit: 3,
+            ..RenderStats::default()
+        };
+        assert!((stats.depth_limit_fraction() - 0.3).abs() < 1e-6);
+        assert_eq!(RenderStats::default().depth_limit_fraction(), 0.0);
+
+        let scene = scene(vec![mirror(1.0)], vec![]);
+        let mut settings = RenderSettings::new(9, 9);
+        settings.max_depth = 0;
+        let (_, stats) = render(&scene, &settings);
+        assert!(stats.pixels_at_depth_limit > 0 && stats.pixels_at_depth_limit < stats.pixels);
+        settings.max_depth = 3;
+        assert_eq!(render(&scene, &settings).1.pixels_at_depth_limit, 0);
+    }
+
+    #[test]
+    fn metals_have_no_diffuse_light() {
+        let scene = scene(vec![], vec![point(1.0, Vec3::new(1.0, 1.0, 0.0))]);
+        let settings = RenderSettings::new(1, 1);
+        let gold = Color::new(0xd4, 0xaf, 0x37, 0xff);
+        let metal = Specularity::Metallic {
+            exponent: 500.0,
+            tint: gold,
+        };
+        // the camera is off the mirror direction, so the highlight is gone
+        assert!(lighting(&scene, &settings, Vec3::J, metal) < 1e-4);
+        let plastic = lighting(&scene, &settings, Vec3::J, Specularity::Specular(500.0));
+        assert!((plastic - Float::sqrt(0.5)).abs() < 1e-4);
+
+        let mut sphere = ball(Color::WHITE);
+        sphere.specularity = metal;
+        let scene = self::scene(vec![sphere], vec![ambient(1.0)]);
+        assert_eq!(center(&scene, &RenderSettings::new(9, 9)), gold);
+    }
+
+    /// the number of pixels showing a sphere in a 32 x 32 frame of a unit
+    /// sphere five units ahead, scaled by scale and fit with fit_camera_dist
+    fn framed_pixels(scale: Float) -> usize {
+        let mut scene = scene(vec![ball(Color::WHITE)], vec![ambient(1.0)]);
+        scene.spheres[0].radius *= scale;
+        scene.spheres[0].center = scale * scene.spheres[0].center;
+        scene.fit_camera_dist();
+        let (frame, _) = render(&scene, &RenderSettings::new(32, 32));
+        colors(&frame)
+            .iter()
+            .filter(|&&c| c == Color::WHITE)
+            .count()
+    }
+
+    #[test]
+    fn fit_scenes_frame_the_same_at_any_scale() {
+        let unit = framed_pixels(1.0);
+        assert!(unit > 100);
+        for scale in [1e-3, 1e3] {
+            assert!(framed_pixels(scale).abs_diff(unit) <= 2, "{scale}");
+        }
+    }
+
+    #[test]
+    fn matcap_ignores_lights_and_materials() {
+        let mut settings = RenderSettings::new(9, 9);
+        settings.mode = RenderMode::Matcap;
+        let red = render(&scene(vec![ball(Color::RED)], vec![]), &settings).0;
+        let blue = render(
+            &scene(vec![ball(Color::BLUE)], vec![ambient(1.0)]),
+            &settings,
+        )
+        .0;
+        assert!(red == blue);
+        assert_ne!(pixel(&red, 9, 4, 4), Color::BLACK);
+        // faces turned towards the light are brighter
+        let lit = matcap(Vec3::new(-0.4, 0.5, -0.75).normalize());
+        let unlit = matcap(Vec3::new(0.4, -0.5, -0.75).normalize());
+        assert!(lit.r > unlit.r);
+    }
+
+    #[test]
+    fn coincident_spheres_break_ties_by_order() {
+        let red = ball(Color::RED);
+        let green = ball(Color::GREEN);
+        let settings = RenderSettings::new(9, 9);
+        let scene = scene(vec![red, green], vec![ambient(1.0)]);
+        assert_eq!(center(&scene, &settings), Color::RED);
+        let mut swapped = scene;
+        swapped.spheres.reverse();
+        assert_eq!(center(&swapped, &settings), Color::GREEN);
+        let hit = intersect_rays(&swapped, &[Vec3::ZERO], &[Vec3::K])[0].unwrap();
+        assert_eq!(hit.sphere, 0);
+    }
+
+    #[test]
+    fn opacity_blends_with_what_is_behind() {
+        let mut scene = scene(vec![ball(Color::RED)], vec![ambient(1.0)]);
+        scene.background = Background::Solid(Color::BLUE);
+        let mut settings = RenderSettings::new(9, 9);
+        assert_eq!(center(&scene, &settings), Color::RED);
+        scene.spheres[0].opacity = 0.0;
+        assert_eq!(center(&scene, &settings), Color::BLUE);
+
+        settings.ordered_transparency = true;
+        assert_eq!(center(&scene, &settings), Color::BLUE);
+        scene.spheres[0].opacity = 0.5;
+        assert_eq!(center(&scene, &settings), Color::new(128, 0, 128, 0xff));
+    }
+
+    #[test]
+    fn edge_mask_marks_object_boundaries() {
+        let objects = [None, Some(0), Some(0), Some(0), Some(0), Some(0)];
+        assert_eq!(
+            edge_mask(&objects, 3),
+            [true, true, false, true, false, false]
+        );
+    }
+
+    #[test]
+    fn tiny_spheres_are_culled_from_camera_rays() {
+        let scene = scene(
+            vec![colored(0.5, Vec3::new(0.0, 0.0, 100.0), Color::RED)],
+            vec![ambient(1.0)],
+        );
+        let mut settings = RenderSettings::new(15, 15);
+        assert_eq!(center(&scene, &settings), Color::RED);
+        settings.min_sphere_pixels = 1.0;
+        assert_eq!(center(&scene, &settings), Color::BLACK);
+    }
+
+    #[test]
+    fn tangent_rays_hit_the_silhouette() {
+        let center = Vec3::new(0.0, 0.0, 5.0);
+        let (t1, t2) = intersect_ray_ball(Vec3::I, Vec3::K, center, 1.0);
+        assert!((t1 - 5.0).abs() < 1e-4 && (t2 - 5.0).abs() < 1e-4);
+        let outside = Vec3::new(1.01, 0.0, 0.0);
+        assert_eq!(
+            intersect_ray_ball(outside, Vec3::K, center, 1.0).0,
+            Float::INFINITY
+        );
+    }
+
+    #[test]
+    fn merged_scenes_render_together() {
+        let mut left = scene(
+            vec![colored(1.0, Vec3::new(-1.5, 0.0, 5.0), Color::RED)],
+            vec![ambient(1.0)],
+        );
+        let right = scene(
+            vec![colored(1.0, Vec3::new(1.5, 0.0, 5.0), Color::GREEN)],
+            vec![],
+        );
+        left.merge(right);
+        let (frame, _) = render(&left, &RenderSettings::new(32, 32));
+        let colors = colors(&frame);
+        assert!(colors.contains(&Color::RED) && colors.contains(&Color::GREEN));
+    }
+
+    #[test]
+    fn masks_give_the_coverage_of_each_sphere() {
+        let scene = scene(
+            vec![
+                ball(Color::RED),
+                colored(3.0, Vec3::new(0.0, 0.0, 10.0), Color::GREEN),
+            ],
+            vec![],
+        );
+        let mut settings = RenderSettings::new(15, 15);
+        let masks = render_masks(&scene, &camera(), &settings);
+        let center = (7 * 15 + 7) as usize;
+        assert_eq!((masks[0][center], masks[1][center]), (1.0, 0.0));
+        assert_eq!((masks[0][0], masks[1][0]), (0.0, 0.0));
+
+        settings.samples = 3;
+        let masks = render_masks(&scene, &camera(), &settings);
+        assert!(masks[0].iter().any(|&m| m > 0.0 && m < 1.0));
+        let mut covered = masks[0].iter().zip(&masks[1]);
+        assert!(covered.all(|(a, b)| a + b <= 1.0 + 1e-6));
+    }
+
+    #[test]
+    fn depth_limit_color_ends_deep_reflections() {
+        let mut scene = scene(vec![mirror(1.0)], vec![]);
+        scene.background = Background::Solid(Color::WHITE);
+        let mut settings = RenderSettings::new(9, 9);
+        settings.max_depth = 0;
+        assert_eq!(center(&scene, &settings), Color::WHITE);
+        settings.depth_limit_color = Some(Color::GREEN);
+        assert_eq!(center(&scene, &settings), Color::GREEN);
+    }
+
+    #[test]
+    fn contact_occlusion_darkens_near_spheres() {
+        let scene = scene(vec![sphere(1.0, Vec3::new(0.0, 2.0, 0.0))], vec![]);
+        let settings = RenderSettings::new(1, 1);
+        let ctx = TraceContext::new(&scene, &settings);
+        assert!((contact_occlusion(&ctx, Vec3::ZERO, Vec3::J) - 0.25).abs() < 1e-6);
+        assert!(contact_occlusion(&ctx, Vec3::new(100.0, 0.0, 0.0), Vec3::J) < 1e-5);
+        // the sphere a point lies on doesn't occlude it
+        assert_eq!(
+            contact_occlusion(&ctx, Vec3::new(0.0, 3.0, 0.0), Vec3::J),
+            0.0
+        );
+    }
+
+    #[test]
+    fn modes_cycle() {
+        let mut mode = RenderMode::default();
+        for expected in [RenderMode::Matcap, RenderMode::Toon, RenderMode::Shaded] {
+            mode = next_mode(mode);
+            assert_eq!(mode, expected);
+        }
+    }
+
+    #[test]
+    fn refinement_halves_the_block_size() {
+        let mut refinement = Refinement::new();
+        let mut sizes = vec![refinement.block_size()];
+        while !refinement.is_done() {
+            refinement.advance();
+            sizes.push(refinement.block_size());
+        }
+        assert_eq!(sizes, [8, 4, 2, 1]);
+        refinement.advance();
+        assert_eq!(refinement.block_size(), 1);
+        refinement.reset();
+        assert_eq!(refinement, Refinement::default());
+    }
+
+    #[test]
+    fn blocks_repeat_their_corner_pixel() {
+        let scene = scene(
+            vec![ball(Color::RED)],
+            vec![ambient(0.2), point(0.8, Vec3::new(2.0, 2.0, 0.0))],
+        );
+        let mut settings = RenderSettings::new(16, 16);
+        settings.block_size = 4;
+        let (frame, stats) = render(&scene, &settings);
+        assert_eq!(stats.primary_rays, 16);
+        for (x, y) in [(5, 6), (9, 10), (15, 15)] {
+            assert_eq!(
+                pixel(&frame, 16, x, y),
+                pixel(&frame, 16, x - x % 4, y - y % 4)
+            );
+        }
+    }
+
+    #[test]
+    fn equal_anisotropic_exponents_match_blinn_phong() {
+        let light = Vec3::new(1.0, 1.0, 0.0);
+        let halfway = (light.normalize() + Vec3::J).normalize();
+        let blinn = halfway.y.powf(50.0);
+        let equal = anisotropic_highlight(Vec3::J, light, Vec3::J, (50.0, 50.0), Vec3::I);
+        assert!((equal - blinn).abs() < 1e-4);
+
+        // the highlight stretches along the brush, so it is brighter there
+        let along = anisotropic_highlight(Vec3::J, light, Vec3::J, (10.0, 1000.0), Vec3::I);
+        let across = anisotropic_highlight(Vec3::J, light, Vec3::J, (10.0, 1000.0), Vec3::K);
+        assert!(along > blinn && across < blinn);
+    }
+
+    #[test]
+    fn specular_highlights_are_clamped() {
+        let scene = scene(vec![], vec![point(2.0, Vec3::new(0.0, 10.0, 0.0))]);
+        let mut settings = RenderSettings::new(1, 1);
+        let spec = Specularity::Specular(100.0);
+        assert!((lighting(&scene, &settings, Vec3::J, spec) - 4.0).abs() < 1e-4);
+        settings.max_specular = Some(0.5);
+        assert!((lighting(&scene, &settings, Vec3::J, spec) - 2.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn ambient_light_color_tints_surfaces() {
+        let light = AmbientLight {
+            intensity: 1.0,
+            color: Color::RED,
+        };
+        let scene = scene(vec![ball(Color::WHITE)], vec![Light::Ambient(light)]);
+        assert_eq!(center(&scene, &RenderSettings::new(9, 9)), Color::RED);
+    }
+
+    #[test]
+    fn frame_hash_is_fnv_1a() {
+        assert_eq!(frame_hash(&[]), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(frame_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+
+        let scene = scene(
+            vec![ball(Color::RED)],
+            vec![point(1.0, Vec3::new(2.0, 2.0, 0.0))],
+        );
+        let settings = RenderSettings::new(16, 16);
+        let hash = render_and_hash(&scene, &camera(), &settings);
+        assert_eq!(render_and_hash(&scene, &camera(), &settings), hash);
+        let mut moved = scene;
+        moved.spheres[0].center.x += 0.5;
+        assert_ne!(render_and_hash(&moved, &camera(), &settings), hash);
+    }
+
+    #[test]
+    fn ordered_transparency_ignores_sphere_order() {
+        let mut front = ball(Color::RED);
+        front.opacity = 0.5;
+        let mut back = colored(2.0, Vec3::new(0.5, 0.0, 8.0), Color::GREEN);
+        back.opacity = 0.3;
+        let mut settings = RenderSettings::new(16, 16);
+        settings.ordered_transparency = true;
+        let mut scene = scene(vec![front, back], vec![ambient(1.0)]);
+        scene.background = Background::Solid(Color::BLUE);
+        let (frame, _) = render(&scene, &settings);
+        scene.spheres.reverse();
+        assert!(render(&scene, &settings).0 == frame);
+    }
+
+    #[test]
+    fn sample_count_follows_the_frame_budget() {
+        assert_eq!(next_sample_count(2, 20.0, 16.0), 1);
+        assert_eq!(next_sample_count(2, 5.0, 16.0), 3);
+        // another sample per axis would more than double the time
+        assert_eq!(next_sample_count(2, 10.0, 16.0), 2);
+        assert_eq!(
+            next_sample_count(MAX_AUTO_SAMPLES, 1.0, 16.0),
+            MAX_AUTO_SAMPLES
+        );
+        assert_eq!(
+            next_sample_count(MIN_AUTO_SAMPLES, 100.0, 16.0),
+            MIN_AUTO_SAMPLES
+        );
+    }
+
+    #[test]
+    fn ray_tree_records_every_ray() {
+        let scene = scene(
+            vec![mirror(0.5)],
+            vec![point(1.0, Vec3::new(0.0, 5.0, 0.0))],
+        );
+        let settings = RenderSettings::new(9, 9);
+        let tree = trace_ray_tree(&scene, &camera(), &settings, 4, 4);
+        assert_eq!(tree.kind, RayKind::Primary);
+        assert_eq!(tree.hit.map(|hit| hit.sphere), Some(0));
+        let kinds: Vec<_> = tree.children.iter().map(|ray| ray.kind).collect();
+        assert_eq!(kinds, [RayKind::Shadow, RayKind::Reflection]);
+        assert_eq!(tree.children[0].color, None);
+        assert_eq!(tree.color, Some(center(&scene, &settings)));
+    }
+
+    #[test]
+    fn distant_spheres_skip_reflections() {
+        let scene = scene(vec![mirror(1.0)], vec![]);
+        let mut settings = RenderSettings::new(9, 9);
+        settings.max_reflection_distance = Some(10.0);
+        assert!(render(&scene, &settings).1.reflection_rays > 0);
+        settings.max_reflection_distance = Some(2.0);
+        assert_eq!(render(&scene, &settings).1.reflection_rays, 0);
+    }
+
+    #[test]
+    fn edge_strength_finds_steps_and_silhouettes() {
+        let flat = edge_strength(&[2.0; 9], &[-Vec3::K; 9], 3);
+        assert!(flat.iter().all(|&e| e == 0.0));
+        let empty = edge_strength(&[Float::INFINITY; 9], &[Vec3::ZERO; 9], 3);
+        assert!(empty.iter().all(|&e| e == 0.0));
+
+        let mut depths = [2.0; 9];
+        depths[4] = Float::INFINITY;
+        let silhouette = edge_strength(&depths, &[-Vec3::K; 9], 3);
+        assert!(silhouette.iter().all(|&e| e == 1.0));
+
+        // a depth step between the second and third columns
+        let depths: Vec<Float> = (0..16).map(|i| if i % 4 < 2 { 1.0 } else { 2.0 }).collect();
+        let step = edge_strength(&depths, &[-Vec3::K; 16], 4);
+        assert_eq!(step[0], 0.0);
+        assert!(step[1] > 0.5);
+    }
+
+    #[test]
+    fn toon_mode_bands_the_light() {
+        let mut scene = scene(
+            vec![ball(Color::WHITE)],
+            vec![point(1.0, Vec3::new(5.0, 5.0, 0.0))],
+        );
+        scene.background = Background::Solid(Color::BLUE);
+        let mut settings = RenderSettings::new(32, 32);
+        let levels = |settings: &RenderSettings| {
+            let (frame, _) = render(&scene, settings);
+            let mut lit: Vec<u8> = colors(&frame)
+                .into_iter()
+                .filter(|&c| c != Color::BLUE && c != UNLIT)
+                .map(|c| c.r)
+                .collect();
+            lit.sort();
+            lit.dedup();
+            lit.len()
+        };
+        assert!(levels(&settings) > 3);
+        settings.mode = RenderMode::Toon;
+        assert_eq!(levels(&settings), 3);
+    }
+
+    #[test]
+    fn grid_lines_lie_on_the_plane() {
+        let grid = GridFloor::new(-1.0, 2.0, Color::GREEN);
+        assert!(grid.is_on_line(0.0, 0.7) && grid.is_on_line(4.03, 0.7));
+        assert!(!grid.is_on_line(1.0, 1.0));
+        let down = -Vec3::J;
+        assert_eq!(
+            grid.line_hit(Vec3::new(2.0, 1.0, 0.5), down, 0.0, 10.0),
+            Some(2.0)
+        );
+        assert_eq!(
+            grid.line_hit(Vec3::new(1.0, 1.0, 1.0), down, 0.0, 10.0),
+            None
+        );
+        assert_eq!(
+            grid.line_hit(Vec3::new(2.0, 1.0, 0.5), down, 0.0, 1.0),
+            None
+        );
+        assert_eq!(grid.line_hit(Vec3::ZERO, Vec3::K, 0.0, 10.0), None);
+
+        // the line straight below the camera shows in the bottom row
+        let mut settings = RenderSettings::new(15, 15);
+        settings.grid = Some(GridFloor::new(-1.0, 100.0, Color::GREEN));
+        let (frame, _) = render(&scene(vec![], vec![]), &settings);
+        assert_eq!(pixel(&frame, 15, 7, 14), Color::GREEN);
+        assert_eq!(pixel(&frame, 15, 7, 0), Color::BLACK);
+    }
+
+    #[test]
+    fn empty_scene_renders_its_background() {
+        let mut scene = scene(vec![], vec![ambient(1.0)]);
+        scene.background = Background::Solid(Color::BLUE);
+        let (frame, stats) = render(&scene, &RenderSettings::new(8, 8));
+        assert!(colors(&frame).iter().all(|&c| c == Color::BLUE));
+        assert_eq!(stats.intersection_tests, 0);
+    }
+
+    #[test]
+    fn material_override_replaces_every_material() {
+        let scene = scene(vec![ball(Color::RED)], vec![ambient(1.0)]);
+        let mut settings = RenderSettings::new(9, 9);
+        settings.material_override = Some(Material {
+            color: Color::GREEN,
+            ..Material::default()
+        });
+        assert_eq!(center(&scene, &settings), Color::GREEN);
+    }
+
+    #[test]
+    fn fireflies_are_removed_and_edges_kept() {
+        let black = [0, 0, 0, 255];
+        let white = [255, 255, 255, 255];
+        let filter = FireflyFilter::new(2.0, 3, 10.0);
+
+        let mut frame: Vec<u8> = (0..25)
+            .flat_map(|i| if i == 12 { white } else { black })
+            .collect();
+        remove_fireflies(&mut frame, 5, filter);
+        assert!(frame.chunks_exact(4).all(|p| p == black));
+
+        let edge: Vec<u8> = (0..25)
+            .flat_map(|i| if i % 5 < 2 { white } else { black })
+            .collect();
+        let mut frame = edge.clone();
+        remove_fireflies(&mut frame, 5, filter);
+        assert!(frame == edge);
+    }
+
+    #[test]
+    fn removed_objects_are_not_rendered() {
+        let mut scene = scene(vec![], vec![ambient(1.0)]);
+        let red = scene.add_object(colored(1.0, Vec3::new(-1.5, 0.0, 5.0), Color::RED));
+        scene.add_object(colored(1.0, Vec3::new(1.5, 0.0, 5.0), Color::GREEN));
+        scene.remove_object(red);
+        let (frame, _) = render(&scene, &RenderSettings::new(32, 32));
+        let colors = colors(&frame);
+        assert!(!colors.contains(&Color::RED) && colors.contains(&Color::GREEN));
+    }
+
+    #[test]
+    fn world_points_project_onto_the_frame() {
+        let scene = scene(vec![], vec![]);
+        let settings = RenderSettings::new(16, 16);
+        let project = |p| world_to_frame(&scene, &settings, &camera(), p);
+        assert_eq!(project(Vec3::new(0.0, 0.0, 5.0)), Some((8.0, 8.0)));
+        // the corner of the viewport is the corner of the frame
+        assert_eq!(project(Vec3::new(0.5, 0.5, 1.0)), Some((16.0, 0.0)));
+        assert_eq!(project(Vec3::new(0.0, 0.0, -1.0)), None);
+    }
 }
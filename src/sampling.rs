@@ -0,0 +1,265 @@
+use crate::geometry::consts::PI;
+
+use crate::geometry::*;
+
+/// a small, fast pseudorandom number generator (splitmix64). it is seeded
+/// explicitly so renders that sample randomly are reproducible
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    /// a generator for the pixel at (x, y) derived from a base seed. each
+    /// pixel's stream depends only on the seed and its coordinates, so pixels
+    /// can be rendered in any order, or split across tiles or threads, and
+    /// still reproduce the same image
+    pub fn for_pixel(seed: u64, x: u32, y: u32) -> Self {
+        let mut rng = Rng::new(seed ^ ((y as u64) << 32 | x as u64));
+        Rng::new(rng.next_u64())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// a uniformly distributed value in [0, 1). only as many bits as the
+    /// mantissa holds are used, so the value never rounds up to 1
+    pub fn next_float(&mut self) -> Float {
+        let bits = Float::MANTISSA_DIGITS;
+        (self.next_u64() >> (64 - bits)) as Float / (1u64 << bits) as Float
+    }
+}
+
+/// a uniformly distributed point on the surface of the unit sphere
+pub fn sample_unit_sphere(rng: &mut Rng) -> Vec3 {
+    let z = 1.0 - 2.0 * rng.next_float();
+    let phi = 2.0 * PI * rng.next_float();
+    let r = Float::sqrt(1.0 - z * z);
+    Vec3::new(r * Float::cos(phi), r * Float::sin(phi), z)
+}
+
+/// a unit direction in the hemisphere around normal, distributed with density
+/// proportional to the cosine of its angle to normal (malley's method: a
+/// uniform point on the unit disc projected up onto the hemisphere)
+pub fn sample_cosine_hemisphere(normal: Vec3, rng: &mut Rng) -> Vec3 {
+    let r = Float::sqrt(rng.next_float());
+    let phi = 2.0 * PI * rng.next_float();
+    let local = Vec3::new(
+        r * Float::cos(phi),
+        r * Float::sin(phi),
+        Float::sqrt(1.0 - r * r),
+    );
+    Mat3x3::rotation_between(Vec3::K, normal) * local
+}
+
+/// how the sample offsets within a pixel are chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Sampler {
+    /// one jittered sample in each cell of an n x n grid
+    #[default]
+    Grid,
+    /// n * n independent uniform samples
+    Random,
+    /// the first n * n points of the 2d halton sequence (bases 2 and 3),
+    /// randomly shifted per pixel. low-discrepancy points cover the pixel
+    /// more evenly than random ones, so they converge with less noise
+    Halton,
+}
+
+impl Sampler {
+    /// n * n sample offsets in the unit square
+    pub fn samples(self, n: u32, rng: &mut Rng) -> Vec<(Float, Float)> {
+        match self {
+            Sampler::Grid => stratified_samples(n, rng),
+            Sampler::Random => (0..n * n)
+                .map(|_| (rng.next_float(), rng.next_float()))
+                .collect(),
+            Sampler::Halton => {
+                // a random toroidal shift keeps the points' even spacing
+                // while decorrelating neighboring pixels
+                let (sx, sy) = (rng.next_float(), rng.next_float());
+                (1..=n * n)
+                    .map(|i| ((halton(i, 2) + sx).fract(), (halton(i, 3) + sy).fract()))
+                    .collect()
+            }
+        }
+    }
+}
+
+/// a per-pixel offset added to colors as they are quantized to 8 bits, so
+/// smooth gradients dither instead of showing bands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    /// a repeating 4 x 4 bayer matrix
+    Ordered,
+    /// white noise
+    Random,
+}
+
+impl Dither {
+    const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+    /// the offset in [0, 1) for the pixel at (x, y)
+    pub fn offset(self, x: u32, y: u32, rng: &mut Rng) -> Float {
+        match self {
+            Dither::Ordered => (Self::BAYER[y as usize % 4][x as usize % 4] as Float + 0.5) / 16.0,
+            Dither::Random => rng.next_float(),
+        }
+    }
+}
+
+/// the index-th element of the halton sequence in the given base, i.e. the
+/// digits of index in base reflected about the radix point
+pub fn halton(mut index: u32, base: u32) -> Float {
+    let mut result = 0.0;
+    let mut f = 1.0;
+    while index > 0 {
+        f /= base as Float;
+        result += f * (index % base) as Float;
+        index /= base;
+    }
+    result
+}
+
+/// divides the unit square into an n x n grid and places one uniformly
+/// jittered sample in each cell, returning the n * n sample offsets in
+/// row-major cell order
+pub fn stratified_samples(n: u32, rng: &mut Rng) -> Vec<(Float, Float)> {
+    let cell = 1.0 / n as Float;
+    (0..n)
+        .flat_map(|j| (0..n).map(move |i| (i, j)))
+        .map(|(i, j)| {
+            (
+                (i as Float + rng.next_float()) * cell,
+                (j as Float + rng.next_float()) * cell,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stratified_samples_fall_in_their_own_cells() {
+        let n = 4;
+        let samples = stratified_samples(n, &mut Rng::new(7));
+        assert_eq!(samples.len(), (n * n) as usize);
+        let cell = 1.0 / n as Float;
+        for (k, &(x, y)) in samples.iter().enumerate() {
+            let (i, j) = (k as u32 % n, k as u32 / n);
+            assert!(x >= i as Float * cell && x < (i + 1) as Float * cell);
+            assert!(y >= j as Float * cell && y < (j + 1) as Float * cell);
+        }
+        assert_eq!(samples, stratified_samples(n, &mut Rng::new(7)));
+        assert_ne!(samples, stratified_samples(n, &mut Rng::new(8)));
+    }
+
+    #[test]
+    fn pixel_streams_depend_only_on_seed_and_position() {
+        let draw = |seed, x, y| {
+            let mut rng = Rng::for_pixel(seed, x, y);
+            [rng.next_u64(), rng.next_u64()]
+        };
+        assert_eq!(draw(1, 3, 4), draw(1, 3, 4));
+        assert_ne!(draw(1, 3, 4), draw(1, 4, 3));
+        assert_ne!(draw(1, 3, 4), draw(2, 3, 4));
+    }
+
+    #[test]
+    fn random_floats_are_in_the_unit_interval() {
+        let mut rng = Rng::new(0);
+        for _ in 0..10_000 {
+            let f = rng.next_float();
+            assert!((0.0..1.0).contains(&f));
+        }
+    }
+
+    #[test]
+    fn cosine_hemisphere_samples_lean_towards_the_normal() {
+        let normal = Vec3::new(1.0, 2.0, -0.5).normalize();
+        let mut rng = Rng::new(3);
+        let n = 10_000;
+        let mut sum = Vec3::ZERO;
+        for _ in 0..n {
+            let dir = sample_cosine_hemisphere(normal, &mut rng);
+            assert!(dir.dot(normal) >= -1e-6);
+            assert!((dir.length() - 1.0).abs() < 1e-4);
+            sum = sum + dir;
+        }
+        // the mean cosine of the distribution is 2/3
+        let mean = sum / n as Float;
+        assert!(mean.normalize().dot(normal) > 0.99);
+        assert!((mean.dot(normal) - 2.0 / 3.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn halton_sequence_starts_with_known_values() {
+        let base_2: Vec<Float> = (1..=4).map(|i| halton(i, 2)).collect();
+        assert_eq!(base_2, [0.5, 0.25, 0.75, 0.125]);
+        let base_3: Vec<Float> = (1..=3).map(|i| halton(i, 3)).collect();
+        for (value, expected) in base_3.iter().zip([1.0 / 3.0, 2.0 / 3.0, 1.0 / 9.0]) {
+            assert!((value - expected).abs() < 1e-6);
+        }
+    }
+
+    /// the largest difference between the share of points in a box
+    /// [0, x) x [0, y) and its area, over a grid of boxes
+    fn discrepancy(points: &[(Float, Float)]) -> Float {
+        let steps = 16;
+        let mut worst: Float = 0.0;
+        for i in 1..=steps {
+            for j in 1..=steps {
+                let (x, y) = (i as Float / steps as Float, j as Float / steps as Float);
+                let inside = points.iter().filter(|p| p.0 < x && p.1 < y).count();
+                worst = worst.max((inside as Float / points.len() as Float - x * y).abs());
+            }
+        }
+        worst
+    }
+
+    #[test]
+    fn halton_points_are_more_even_than_random_ones() {
+        let mut rng = Rng::new(11);
+        let halton = discrepancy(&Sampler::Halton.samples(8, &mut rng));
+        let random = discrepancy(&Sampler::Random.samples(8, &mut rng));
+        assert!(halton < random, "{halton} >= {random}");
+    }
+
+    #[test]
+    fn every_sampler_returns_n_squared_offsets_in_the_pixel() {
+        for sampler in [Sampler::Grid, Sampler::Random, Sampler::Halton] {
+            let samples = sampler.samples(3, &mut Rng::new(5));
+            assert_eq!(samples.len(), 9);
+            assert!(samples
+                .iter()
+                .all(|&(x, y)| (0.0..1.0).contains(&x) && (0.0..1.0).contains(&y)));
+        }
+    }
+
+    #[test]
+    fn ordered_dither_covers_every_offset_once_per_tile() {
+        let mut rng = Rng::new(0);
+        let mut offsets: Vec<Float> = (0..4)
+            .flat_map(|y| (0..4).map(move |x| (x, y)))
+            .map(|(x, y)| Dither::Ordered.offset(x, y, &mut rng))
+            .collect();
+        offsets.sort_by(Float::total_cmp);
+        let expected: Vec<Float> = (0..16).map(|i| (i as Float + 0.5) / 16.0).collect();
+        assert_eq!(offsets, expected);
+        assert_eq!(
+            Dither::Ordered.offset(1, 2, &mut rng),
+            Dither::Ordered.offset(5, 6, &mut rng)
+        );
+    }
+}
@@ -0,0 +1,683 @@
+//! a minimal line-oriented scene description format, e.g.
+//!
+//! ```text
+//! # a red sphere on an orange floor
+//! background color=#ffffff
+//! sphere 0,-1,3 r=1 color=#b20d30 spec=500
+//! sphere 0,-5001,0 r=5000 color=#c17817 spec=1000 refl=0.5
+//! light ambient i=0.2
+//! light point 2,1,0 i=0.6
+//! light directional 1,4,4 i=0.2
+//! ```
+//!
+//! each line is a keyword followed by positional values and key=value
+//! parameters separated by whitespace. a `#` at the start of a token begins a
+//! comment that runs to the end of the line.
+//!
+//! | line                      | parameters (defaults)                                                                                                                                                           |
+//! |---------------------------|---------------------------------------------------------------------------------------------------------------------------------------------------------------------------------|
+//! | `sphere x,y,z`            | `r` (1), `material` (none), `color` (#ffffff), `spec` (matte), `refl` (0), `tint` (#ffffff), `metal` (none), `opacity` (1), `grid` (none), `spacing` (3r each), `brush` (0,1,0) |
+//! | `light ambient`           | `i`, `color` (#ffffff)                                                                                                                                                          |
+//! | `light point x,y,z`       | `i`, `r` (0), `path` (none), `pulse` (none)                                                                                                                                     |
+//! | `light directional x,y,z` | `i`                                                                                                                                                                             |
+//! | `background`              | `color`, `top` and `bottom` for a gradient, or `zenith`, `horizon` and `nadir` for a sky                                                                                        |
+//! | `sky_light`               | `i`                                                                                                                                                                             |
+//! | `viewport w,h`            |                                                                                                                                                                                 |
+//! | `camera_dist d`           |                                                                                                                                                                                 |
+//! | `specular phong`          | or `specular blinn-phong`                                                                                                                                                       |
+//!
+//! colors are `#rrggbb` or `#rrggbbaa` and `spec` is either a positive
+//! exponent, two exponents `eu,ev` for a highlight sharpened by `eu` along
+//! the `brush` direction and by `ev` across it, or `matte`. a scene without
+//! `background`, `viewport` or `camera_dist` lines gets a white background, a
+//! 2 x 1.5 viewport and a camera distance of 1.
+//!
+//! `material` starts a sphere from a preset (plastic, rubber, metal, mirror
+//! or glass) whose values the other parameters override. on the metal
+//! preset, `color` sets the metal's color and `spec` its exponents, so it
+//! stays a metal. `metal` makes a
+//! sphere a metal of that color, with no diffuse shading and its highlight
+//! and reflections tinted; its `spec` exponent defaults to 500.
+//! `grid=nx,ny,nz` repeats the sphere in a grid along the x, y and z axes,
+//! `spacing` apart, starting from its position. a point light's `path` is
+//! keyframes `t:x,y,z` separated by `;` that it moves between over time in
+//! seconds, and `pulse` is the amplitude and frequency `a,f` of a sine wave
+//! scaling its intensity. `sky_light` lights surfaces with the background
+//! color in the direction of their normal; without it the background
+//! contributes no light.
+
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use crate::geometry::*;
+
+/// an error in a scene description, with the 1-based line it occurred on
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+/// the positional values and key=value parameters following a keyword
+struct Args<'a> {
+    positional: Vec<&'a str>,
+    named: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Args<'a> {
+    fn new(tokens: &[&'a str]) -> Self {
+        let mut args = Args {
+            positional: Vec::new(),
+            named: Vec::new(),
+        };
+        for token in tokens {
+            match token.split_once('=') {
+                Some((key, value)) => args.named.push((key, value)),
+                None => args.positional.push(token),
+            }
+        }
+        args
+    }
+
+    /// checks that there are exactly n positional values and no parameters
+    /// other than the allowed keys
+    fn expect(&self, n: usize, allowed: &[&str]) -> Result<(), String> {
+        if self.positional.len() != n {
+            return Err(format!(
+                "expected {n} positional value(s), found {}",
+                self.positional.len()
+            ));
+        }
+        match self.named.iter().find(|(key, _)| !allowed.contains(key)) {
+            Some((key, _)) => Err(format!("unknown parameter `{key}`")),
+            None => Ok(()),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&'a str> {
+        self.named
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| *value)
+    }
+
+    fn require(&self, key: &str) -> Result<&'a str, String> {
+        self.get(key)
+            .ok_or_else(|| format!("missing parameter `{key}`"))
+    }
+}
+
+fn parse_float(s: &str) -> Result<Float, String> {
+    s.parse()
+        .map_err(|_| format!("expected a number, found `{s}`"))
+}
+
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let components = s
+        .split(',')
+        .map(parse_float)
+        .collect::<Result<Vec<_>, _>>()?;
+    match components[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!("expected a vector x,y,z, found `{s}`")),
+    }
+}
+
+fn parse_counts(s: &str) -> Result<[u32; 3], String> {
+    let invalid = || format!("expected counts nx,ny,nz, found `{s}`");
+    let counts = s
+        .split(',')
+        .map(|n| n.parse().map_err(|_| invalid()))
+        .collect::<Result<Vec<u32>, _>>()?;
+    counts.try_into().map_err(|_| invalid())
+}
+
+fn parse_color(s: &str) -> Result<Color, String> {
+    let invalid = || format!("expected a color #rrggbb or #rrggbbaa, found `{s}`");
+    let hex = s.strip_prefix('#').ok_or_else(invalid)?;
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+    let a = if hex.len() == 8 { channel(6)? } else { 0xff };
+    Ok(Color::new(channel(0)?, channel(2)?, channel(4)?, a))
+}
+
+fn parse_specularity(s: &str) -> Result<Specularity, String> {
+    if s == "matte" {
+        return Ok(Specularity::Matte);
+    }
+    let invalid =
+        || format!("expected positive specular exponents e or eu,ev or `matte`, found `{s}`");
+    let exponents = s
+        .split(',')
+        .map(parse_float)
+        .collect::<Result<Vec<_>, _>>()?;
+    if !exponents.iter().all(|e| e.is_finite() && *e > 0.0) {
+        return Err(invalid());
+    }
+    match exponents[..] {
+        [exponent] => Ok(Specularity::Specular(exponent)),
+        [exponent_u, exponent_v] => Ok(Specularity::Anisotropic {
+            exponent_u,
+            exponent_v,
+            brush: Vec3::J,
+            metal: None,
+        }),
+        _ => Err(invalid()),
+    }
+}
+
+/// parses keyframes t:x,y,z separated by semicolons, in increasing time
+fn parse_path(s: &str) -> Result<Vec<(Float, Vec3)>, String> {
+    let keyframes = s
+        .split(';')
+        .map(|keyframe| match keyframe.split_once(':') {
+            Some((t, position)) => Ok((parse_float(t)?, parse_vec3(position)?)),
+            None => Err(format!("expected a keyframe t:x,y,z, found `{keyframe}`")),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if keyframes.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+        return Err(format!("keyframe times must increase, found `{s}`"));
+    }
+    Ok(keyframes)
+}
+
+/// parses a pulse amplitude,frequency
+fn parse_pulse(s: &str) -> Result<(Float, Float), String> {
+    match s
+        .split(',')
+        .map(parse_float)
+        .collect::<Result<Vec<_>, _>>()?[..]
+    {
+        [amplitude, frequency] => Ok((amplitude, frequency)),
+        _ => Err(format!("expected a pulse amplitude,frequency, found `{s}`")),
+    }
+}
+
+/// applies the line with the given tokens to scene
+fn parse_line(scene: &mut Scene, tokens: &[&str]) -> Result<(), String> {
+    let (keyword, rest) = tokens.split_first().expect("line has no tokens");
+    match *keyword {
+        "sphere" => {
+            let args = Args::new(rest);
+            args.expect(
+                1,
+                &[
+                    "r", "material", "color", "spec", "refl", "tint", "metal", "opacity", "grid",
+                    "spacing", "brush",
+                ],
+            )?;
+            let material = match args.get("material") {
+                Some(name) => Material::preset(name).ok_or_else(|| {
+                    format!(
+                        "unknown material `{name}`, expected one of {}",
+                        Material::PRESETS.join(", ")
+                    )
+                })?,
+                None => Material::default(),
+            };
+            let mut sphere = Sphere::with_material(
+                args.get("r").map(parse_float).transpose()?.unwrap_or(1.0),
+                parse_vec3(args.positional[0])?,
+                material,
+            );
+            if let Some(color) = args.get("color") {
+                sphere.color = parse_color(color)?;
+                // a metal is shaded with its tint rather than its color
+                if let Specularity::Metallic { tint, .. } = &mut sphere.specularity {
+                    *tint = sphere.color;
+                }
+            }
+            if let Some(spec) = args.get("spec") {
+                sphere.specularity = match (parse_specularity(spec)?, sphere.specularity) {
+                    (Specularity::Specular(exponent), Specularity::Metallic { tint, .. }) => {
+                        Specularity::Metallic { exponent, tint }
+                    }
+                    (
+                        Specularity::Anisotropic {
+                            exponent_u,
+                            exponent_v,
+                            brush,
+                            ..
+                        },
+                        Specularity::Metallic { tint, .. },
+                    ) => Specularity::Anisotropic {
+                        exponent_u,
+                        exponent_v,
+                        brush,
+                        metal: Some(tint),
+                    },
+                    (specularity, _) => specularity,
+                };
+            }
+            if let Some(refl) = args.get("refl") {
+                sphere.reflectiveness = parse_float(refl)?;
+            }
+            if let Some(tint) = args.get("tint") {
+                sphere.reflection_tint = parse_color(tint)?;
+            }
+            if let Some(opacity) = args.get("opacity") {
+                sphere.opacity = parse_float(opacity)?;
+            }
+            if let Some(brush) = args.get("brush") {
+                let Specularity::Anisotropic { brush: b, .. } = &mut sphere.specularity else {
+                    return Err("`brush` needs two specular exponents eu,ev".into());
+                };
+                *b = parse_vec3(brush)?;
+            }
+            if let Some(metal) = args.get("metal") {
+                let tint = parse_color(metal)?;
+                sphere.specularity = match sphere.specularity {
+                    Specularity::Specular(exponent) | Specularity::Metallic { exponent, .. } => {
+                        Specularity::Metallic { exponent, tint }
+                    }
+                    Specularity::Anisotropic {
+                        exponent_u,
+                        exponent_v,
+                        brush,
+                        ..
+                    } => Specularity::Anisotropic {
+                        exponent_u,
+                        exponent_v,
+                        brush,
+                        metal: Some(tint),
+                    },
+                    Specularity::Matte if args.get("spec").is_none() => Specularity::Metallic {
+                        exponent: 500.0,
+                        tint,
+                    },
+                    Specularity::Matte => {
+                        return Err("a metal needs a specular exponent, not `matte`".into())
+                    }
+                };
+            }
+            match args.get("grid") {
+                Some(grid) => {
+                    let counts = parse_counts(grid)?;
+                    let spacing = match args.get("spacing") {
+                        Some(spacing) => parse_vec3(spacing)?,
+                        None => Vec3::new(1.0, 1.0, 1.0) * (3.0 * sphere.radius),
+                    };
+                    for copy in sphere.grid(counts, spacing) {
+                        scene.add_object(copy);
+                    }
+                }
+                None => {
+                    scene.add_object(sphere);
+                }
+            }
+        }
+        "light" => {
+            let (kind, rest) = rest
+                .split_first()
+                .ok_or("expected a light kind: ambient, point or directional")?;
+            let args = Args::new(rest);
+            let light = match *kind {
+                "ambient" => {
+                    args.expect(0, &["i", "color"])?;
+                    let mut light = AmbientLight::new(parse_float(args.require("i")?)?);
+                    if let Some(color) = args.get("color") {
+                        light.color = parse_color(color)?;
+                    }
+                    Light::Ambient(light)
+                }
+                "point" => {
+                    args.expect(1, &["i", "r", "path", "pulse"])?;
+                    let mut light = PointLight::new(
+                        parse_float(args.require("i")?)?,
+                        parse_vec3(args.positional[0])?,
+                    );
+                    if let Some(radius) = args.get("r") {
+                        light.radius = parse_float(radius)?;
+                    }
+                    if args.get("path").is_some() || args.get("pulse").is_some() {
+                        light.animation = Some(LightAnimation {
+                            keyframes: args
+                                .get("path")
+                                .map(parse_path)
+                                .transpose()?
+                                .unwrap_or_default(),
+                            pulse: args.get("pulse").map(parse_pulse).transpose()?,
+                        });
+                    }
+                    Light::Point(light)
+                }
+                "directional" => {
+                    args.expect(1, &["i"])?;
+                    Light::Directional(DirectionalLight::new(
+                        parse_float(args.require("i")?)?,
+                        parse_vec3(args.positional[0])?,
+                    ))
+                }
+                _ => return Err(format!("unknown light kind `{kind}`")),
+            };
+            scene.lights.push(light);
+        }
+        "background" => {
+            let args = Args::new(rest);
+            args.expect(0, &["color", "top", "bottom", "zenith", "horizon", "nadir"])?;
+            let color = |key| args.get(key).map(parse_color).transpose();
+            scene.background = match (
+                color("color")?,
+                color("top")?,
+                color("bottom")?,
+                color("zenith")?,
+                color("horizon")?,
+                color("nadir")?,
+            ) {
+                (Some(color), None, None, None, None, None) => Background::Solid(color),
+                (None, Some(top), Some(bottom), None, None, None) => {
+                    Background::Gradient { top, bottom }
+                }
+                (None, None, None, Some(zenith), Some(horizon), Some(nadir)) => {
+                    Background::ProceduralSky {
+                        zenith,
+                        horizon,
+                        nadir,
+                    }
+                }
+                _ => {
+                    return Err("expected either `color`, `top` and `bottom`, or `zenith`, \
+                                `horizon` and `nadir`"
+                        .into())
+                }
+            };
+        }
+        "sky_light" => {
+            let args = Args::new(rest);
+            args.expect(0, &["i"])?;
+            scene.sky_light = Some(parse_float(args.require("i")?)?);
+        }
+        "viewport" => {
+            let args = Args::new(rest);
+            args.expect(1, &[])?;
+            let size = args.positional[0]
+                .split(',')
+                .map(parse_float)
+                .collect::<Result<Vec<_>, _>>()?;
+            scene.viewport = match size[..] {
+                [w, h] => Surface::new(w, h),
+                _ => {
+                    return Err(format!(
+                        "expected a size w,h, found `{}`",
+                        args.positional[0]
+                    ))
+                }
+            };
+        }
+        "camera_dist" => {
+            let args = Args::new(rest);
+            args.expect(1, &[])?;
+            scene.camera_dist = parse_float(args.positional[0])?;
+        }
+        "specular" => {
+            let args = Args::new(rest);
+            args.expect(1, &[])?;
+            scene.specular_model = match args.positional[0] {
+                "phong" => SpecularModel::Phong,
+                "blinn-phong" => SpecularModel::BlinnPhong,
+                model => return Err(format!("unknown specular model `{model}`")),
+            };
+        }
+        _ => return Err(format!("unknown keyword `{keyword}`")),
+    }
+    Ok(())
+}
+
+/// parses a scene description, reporting the line of the first error
+pub fn parse_scene(src: &str) -> Result<Scene, ParseError> {
+    let mut scene = Scene {
+        spheres: Vec::new(),
+        background: Background::Solid(Color::WHITE),
+        viewport: Surface::new(2.0, 1.5),
+        camera_dist: 1.0,
+        lights: Vec::new(),
+        specular_model: SpecularModel::Phong,
+        max_intensity: None,
+        sky_light: None,
+    };
+
+    for (i, line) in src.lines().enumerate() {
+        let tokens: Vec<&str> = line
+            .split_whitespace()
+            .take_while(|token| !token.starts_with('#'))
+            .collect();
+        if tokens.is_empty() {
+            continue;
+        }
+        parse_line(&mut scene, &tokens).map_err(|message| ParseError {
+            line: i + 1,
+            message,
+        })?;
+    }
+
+    Ok(scene)
+}
+
+/// parses a scene description from raw bytes, e.g. fetched over the network
+/// in the browser where there is no filesystem. the bytes must be utf-8
+pub fn parse_scene_bytes(bytes: &[u8]) -> Result<Scene, ParseError> {
+    let src = std::str::from_utf8(bytes).map_err(|err| {
+        let valid = &bytes[..err.valid_up_to()];
+        ParseError {
+            line: valid.iter().filter(|&&b| b == b'\n').count() + 1,
+            message: "invalid utf-8".into(),
+        }
+    })?;
+    parse_scene(src)
+}
+
+/// reads and parses the scene description at path
+pub fn load_scene(path: impl AsRef<Path>) -> Result<Scene, Box<dyn Error>> {
+    let bytes = std::fs::read(path)?;
+    Ok(parse_scene_bytes(&bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE: &str = "\
+# a red sphere on an orange floor
+background color=#ffffff
+sphere 0,-1,3 r=1 color=#b20d30 spec=500
+sphere 0,-5001,0 r=5000 color=#c17817 spec=1000 refl=0.5  # the floor
+
+light ambient i=0.2
+light point 2,1,0 i=0.6
+light directional 1,4,4 i=0.2
+";
+
+    fn sphere(src: &str) -> Sphere {
+        let mut scene = parse_scene(src).unwrap();
+        assert_eq!(scene.spheres.len(), 1);
+        scene.spheres.remove(0)
+    }
+
+    fn error(src: &str) -> ParseError {
+        match parse_scene(src) {
+            Ok(_) => panic!("`{src}` parsed"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn parses_spheres_and_lights() {
+        let scene = parse_scene(SCENE).unwrap();
+        assert_eq!(scene.spheres.len(), 2);
+        let red = &scene.spheres[0];
+        assert_eq!(red.center, Vec3::new(0.0, -1.0, 3.0));
+        assert_eq!(red.radius, 1.0);
+        assert_eq!(red.color, Color::new(0xb2, 0x0d, 0x30, 0xff));
+        assert!(matches!(red.specularity, Specularity::Specular(s) if s == 500.0));
+        assert_eq!(red.reflectiveness, 0.0);
+        let floor = &scene.spheres[1];
+        assert_eq!(floor.radius, 5000.0);
+        assert_eq!(floor.reflectiveness, 0.5);
+
+        assert_eq!(scene.lights.len(), 3);
+        assert!(
+            matches!(scene.lights[0], Light::Ambient(AmbientLight { intensity, color })
+            if intensity == 0.2 && color == Color::WHITE)
+        );
+        assert!(matches!(&scene.lights[1], Light::Point(light)
+            if light.intensity == 0.6 && light.position == Vec3::new(2.0, 1.0, 0.0)));
+        assert!(
+            matches!(scene.lights[2], Light::Directional(DirectionalLight { intensity, dir })
+            if intensity == 0.2 && dir == Vec3::new(1.0, 4.0, 4.0))
+        );
+    }
+
+    #[test]
+    fn missing_lines_get_defaults() {
+        let scene = parse_scene("").unwrap();
+        assert!(scene.spheres.is_empty() && scene.lights.is_empty());
+        assert_eq!(scene.background, Background::Solid(Color::WHITE));
+        assert_eq!((scene.viewport.w, scene.viewport.h), (2.0, 1.5));
+        assert_eq!(scene.camera_dist, 1.0);
+    }
+
+    #[test]
+    fn errors_report_their_line() {
+        let err = error("# comment\n\nsphere 0,0,0 r=1\nsphere 0,0 r=1\n");
+        assert_eq!(err.line, 4);
+        assert_eq!(
+            err.to_string(),
+            "line 4: expected a vector x,y,z, found `0,0`"
+        );
+        assert_eq!(error("light point 0,0,0").line, 1);
+        assert_eq!(
+            error("sphere 0,0,0 size=2").message,
+            "unknown parameter `size`"
+        );
+        assert_eq!(error("\n\ncube 0,0,0").message, "unknown keyword `cube`");
+        assert_eq!(error("sphere 0,0,0 color=red").line, 1);
+    }
+
+    #[test]
+    fn parses_backgrounds() {
+        let gradient = parse_scene("background top=#0000ff bottom=#ffffff").unwrap();
+        assert_eq!(
+            gradient.background,
+            Background::Gradient {
+                top: Color::BLUE,
+                bottom: Color::WHITE
+            }
+        );
+        let sky = parse_scene("background zenith=#0000ff horizon=#ffffff nadir=#000000ff").unwrap();
+        assert!(matches!(sky.background, Background::ProceduralSky { .. }));
+        assert!(parse_scene("background top=#0000ff").is_err());
+    }
+
+    #[test]
+    fn parses_tinted_ambient_light() {
+        let scene = parse_scene("light ambient i=0.3 color=#0000ff").unwrap();
+        assert!(
+            matches!(scene.lights[0], Light::Ambient(AmbientLight { color, .. })
+            if color == Color::BLUE)
+        );
+    }
+
+    #[test]
+    fn parses_light_animation() {
+        let scene = parse_scene("light point 0,0,0 i=1 path=0:0,0,0;2:4,0,0 pulse=0.5,1").unwrap();
+        let Light::Point(light) = &scene.lights[0] else {
+            panic!("not a point light");
+        };
+        let animation = light.animation.as_ref().unwrap();
+        assert_eq!(animation.keyframes.len(), 2);
+        assert_eq!(animation.pulse, Some((0.5, 1.0)));
+        assert_eq!(light.at(1.0).position, Vec3::new(2.0, 0.0, 0.0));
+
+        assert!(parse_scene("light point 0,0,0 i=1 path=1:0,0,0;1:4,0,0").is_err());
+        assert!(parse_scene("light point 0,0,0 i=1 path=2:0,0,0;1:4,0,0").is_err());
+    }
+
+    #[test]
+    fn presets_are_overridden_by_parameters() {
+        let plastic = sphere("sphere 0,0,0 material=plastic refl=0.3");
+        assert!(matches!(plastic.specularity, Specularity::Specular(s) if s == 100.0));
+        assert_eq!(plastic.reflectiveness, 0.3);
+        let err = error("sphere 0,0,0 material=chrome");
+        assert!(err.message.starts_with("unknown material `chrome`"));
+    }
+
+    #[test]
+    fn metal_preset_stays_a_metal() {
+        let gold = Color::new(0xd4, 0xaf, 0x37, 0xff);
+        let metal = sphere("sphere 0,0,0 material=metal color=#d4af37 spec=50");
+        assert_eq!(metal.color, gold);
+        assert!(
+            matches!(metal.specularity, Specularity::Metallic { exponent, tint }
+            if exponent == 50.0 && tint == gold)
+        );
+
+        let brushed = sphere("sphere 0,0,0 material=metal spec=10,200");
+        assert!(matches!(
+            brushed.specularity,
+            Specularity::Anisotropic { metal: Some(_), .. }
+        ));
+        let matte = sphere("sphere 0,0,0 material=metal spec=matte");
+        assert!(matches!(matte.specularity, Specularity::Matte));
+    }
+
+    #[test]
+    fn metal_parameter_makes_a_metal() {
+        let red = Color::new(0xff, 0, 0, 0xff);
+        let metal = sphere("sphere 0,0,0 metal=#ff0000");
+        assert!(
+            matches!(metal.specularity, Specularity::Metallic { exponent, tint }
+            if exponent == 500.0 && tint == red)
+        );
+        let shiny = sphere("sphere 0,0,0 spec=20 metal=#ff0000");
+        assert!(
+            matches!(shiny.specularity, Specularity::Metallic { exponent, .. }
+            if exponent == 20.0)
+        );
+        assert!(parse_scene("sphere 0,0,0 spec=matte metal=#ff0000").is_err());
+    }
+
+    #[test]
+    fn grids_repeat_the_sphere() {
+        let scene = parse_scene("sphere 0,0,0 r=1 grid=2,1,3").unwrap();
+        assert_eq!(scene.spheres.len(), 6);
+        assert!(scene
+            .spheres
+            .iter()
+            .any(|s| s.center == Vec3::new(3.0, 0.0, 6.0)));
+        let ids: std::collections::HashSet<_> = scene.spheres.iter().map(|s| s.id).collect();
+        assert_eq!(ids.len(), 6);
+    }
+
+    #[test]
+    fn bytes_and_files_parse_like_strings() {
+        let from_str = parse_scene(SCENE).unwrap().to_json();
+        let from_bytes = parse_scene_bytes(SCENE.as_bytes()).unwrap().to_json();
+        assert_eq!(from_bytes, from_str);
+
+        let path = std::env::temp_dir().join(format!("scene-file-test-{}.txt", std::process::id()));
+        std::fs::write(&path, SCENE).unwrap();
+        let from_file = load_scene(&path).map(|scene| scene.to_json());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(from_file.unwrap(), from_str);
+    }
+
+    #[test]
+    fn invalid_utf8_reports_its_line() {
+        let Err(err) = parse_scene_bytes(b"sphere 0,0,0\nsphere 0,0,\xff") else {
+            panic!("invalid utf-8 parsed");
+        };
+        assert_eq!(err.line, 2);
+    }
+}
@@ -0,0 +1,76 @@
+//! a canonical json dump of a loaded scene, for tooling and for diffing
+//! scenes, and a reader for it. the scene types derive serde's Serialize
+//! and Deserialize, so every field is written, including ones left at
+//! their defaults, in the order the types declare them. json can't hold
+//! non-finite numbers, so serde_json writes them as `null` and a scene
+//! containing them doesn't read back.
+//! object handles aren't written; spheres read back get fresh ones, as
+//! from a scene file.
+
+use crate::geometry::Scene;
+
+impl Scene {
+    /// the scene as pretty-printed json, so equal scenes always produce
+    /// identical text
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("scenes serialize to json")
+    }
+
+    /// reads a scene from json in the form to_json writes
+    pub fn from_json(src: &str) -> Result<Scene, serde_json::Error> {
+        serde_json::from_str(src)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::*;
+    use crate::scene_file::parse_scene;
+
+    #[test]
+    fn json_round_trips_through_the_canonical_form() {
+        let scene = parse_scene(
+            "background zenith=#0000ff horizon=#ffffff nadir=#000000ff
+             sphere 0,-1,3 r=1 color=#b20d30 spec=500 opacity=0.5
+             sphere 0,0,0 material=metal spec=10,200 brush=1,0,0
+             light ambient i=0.2 color=#102030
+             light point 2,1,0 i=0.6 r=0.1 path=0:0,0,0;1:1,1,1 pulse=0.5,2
+             light directional 1,4,4 i=0.2
+             sky_light i=0.3
+             specular blinn-phong",
+        )
+        .unwrap();
+        let json = scene.to_json();
+        let read = Scene::from_json(&json).unwrap();
+        assert_eq!(read.to_json(), json);
+        assert_eq!(read.spheres.len(), 2);
+        assert_eq!(read.sky_light, Some(0.3));
+    }
+
+    #[test]
+    fn json_includes_defaults() {
+        let json = parse_scene("sphere 0,0,0").unwrap().to_json();
+        for field in ["\"reflection_tint\"", "\"visible_primary\"", "\"opacity\""] {
+            assert!(json.contains(field), "{field}");
+        }
+        assert!(!json.contains("\"id\""));
+    }
+
+    #[test]
+    fn spheres_read_from_json_get_fresh_handles() {
+        let json = parse_scene("sphere 0,0,0\nsphere 3,0,0").unwrap().to_json();
+        let (a, b) = (
+            Scene::from_json(&json).unwrap(),
+            Scene::from_json(&json).unwrap(),
+        );
+        assert!(a.spheres.iter().all(|s| s.id.is_some()));
+        assert_ne!(a.spheres[0].id, a.spheres[1].id);
+        assert_ne!(a.spheres[0].id, b.spheres[0].id);
+    }
+
+    #[test]
+    fn invalid_json_is_an_error() {
+        assert!(Scene::from_json("{").is_err());
+        assert!(Scene::from_json("{\"spheres\": []}").is_err());
+    }
+}